@@ -1,15 +1,26 @@
 use crate::domains::auth_service::AuthService;
-use crate::domains::dto::auth::{LoginRequestDto, LogoutRequestDto, RegisterRequestDto};
+use crate::domains::dto::auth::{
+    LoginRequestDto, LogoutRequestDto, ProfileImageQueryDto, ProfileImageResponseDto,
+    RegisterRequestDto,
+};
 use crate::errors::AppError;
+use crate::models::profile_image::{
+    ProfileImageFormat, ProfileImageVariant, MAX_PROFILE_IMAGE_UPLOAD_BYTES,
+};
+use crate::models::user::User;
 use crate::repositories::auth_repository::AuthRepositoryImpl;
-use actix_web::{web, HttpResponse};
+use actix_files::NamedFile;
+use actix_multipart::Multipart;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::TryStreamExt;
 
 pub async fn register_handler(
     service: web::Data<AuthService<AuthRepositoryImpl>>,
     req: web::Json<RegisterRequestDto>,
 ) -> Result<HttpResponse, AppError> {
     match service
-        .register_user(&req.username, &req.password, &req.role, req.area_id)
+        .register_user(&req.username, &req.password, &req.role)
         .await
     {
         Ok(response) => Ok(HttpResponse::Created().json(response)),
@@ -37,13 +48,71 @@ pub async fn logout_handler(
     }
 }
 
+pub async fn revoke_sessions_handler(
+    service: web::Data<AuthService<AuthRepositoryImpl>>,
+    user: web::ReqData<User>,
+) -> Result<HttpResponse, AppError> {
+    match service.revoke_all_sessions(user.id).await {
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+        Err(err) => Err(err),
+    }
+}
+
 pub async fn user_profile_image_handler(
     service: web::Data<AuthService<AuthRepositoryImpl>>,
     path: web::Path<i32>,
+    query: web::Query<ProfileImageQueryDto>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, AppError> {
     let user_id = path.into_inner();
-    let profile_image_byte = service.get_resized_profile_image_byte(user_id).await?;
-    Ok(HttpResponse::Ok()
-        .content_type("image/png")
-        .body(profile_image_byte))
+    let variant = ProfileImageVariant::new(query.w, query.h, query.format.as_deref())?;
+    let profile_image_path = service
+        .get_resized_profile_image_path(user_id, variant)
+        .await?;
+
+    // ETag / Last-Modified の付与と 304 の判定は NamedFile に任せる
+    let mut response = NamedFile::open(profile_image_path)
+        .map_err(|_| AppError::NotFound)?
+        .into_response(&req);
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(variant.format.content_type()),
+    );
+    Ok(response)
+}
+
+// multipart/form-data の image フィールドで受け取った画像をプロフィール画像にする
+pub async fn update_user_image_handler(
+    service: web::Data<AuthService<AuthRepositoryImpl>>,
+    user: web::ReqData<User>,
+    mut payload: Multipart,
+) -> Result<HttpResponse, AppError> {
+    let mut uploaded_image = None;
+    while let Some(mut field) = payload.try_next().await.map_err(|_| AppError::BadRequest)? {
+        if field.name() != Some("image") {
+            continue;
+        }
+        let format = field
+            .content_type()
+            .and_then(|mime| ProfileImageFormat::from_content_type(mime.essence_str()))
+            .ok_or(AppError::BadRequest)?;
+
+        let mut image_byte = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(|_| AppError::BadRequest)? {
+            if image_byte.len() + chunk.len() > MAX_PROFILE_IMAGE_UPLOAD_BYTES {
+                return Err(AppError::BadRequest);
+            }
+            image_byte.extend_from_slice(&chunk);
+        }
+        uploaded_image = Some((format, image_byte));
+    }
+    let (format, image_byte) = uploaded_image.ok_or(AppError::BadRequest)?;
+
+    match service
+        .update_profile_image(user.id, format, image_byte)
+        .await
+    {
+        Ok(profile_image) => Ok(HttpResponse::Ok().json(ProfileImageResponseDto { profile_image })),
+        Err(err) => Err(err),
+    }
 }
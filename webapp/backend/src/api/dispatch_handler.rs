@@ -0,0 +1,156 @@
+use crate::domains::dispatch_service::DispatchService;
+use crate::domains::dto::dispatch::{
+    CommitDispatchPlanRequestDto, PreviewDispatchRequestDto, UpdateAutoDispatchRequestDto,
+};
+use crate::domains::order_service::OrderService;
+use crate::errors::AppError;
+use crate::middlewares::authenticated_user::AuthenticatedUser;
+use crate::repositories::auth_repository::AuthRepositoryImpl;
+use crate::repositories::dispatch_repository::DispatchRepositoryImpl;
+use crate::repositories::map_repository::MapRepositoryImpl;
+use crate::repositories::order_repository::OrderRepositoryImpl;
+use crate::repositories::tow_truck_repository::TowTruckRepositoryImpl;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use serde::Deserialize;
+
+pub async fn get_auto_dispatch_handler(
+    service: web::Data<
+        DispatchService<
+            DispatchRepositoryImpl,
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    identity: AuthenticatedUser,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    match service
+        .get_auto_dispatch_setting(path.into_inner(), identity.area_scope()?)
+        .await
+    {
+        Ok(setting) => Ok(HttpResponse::Ok().json(setting)),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn update_auto_dispatch_handler(
+    service: web::Data<
+        DispatchService<
+            DispatchRepositoryImpl,
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    identity: AuthenticatedUser,
+    path: web::Path<i32>,
+    req: web::Json<UpdateAutoDispatchRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    match service
+        .update_auto_dispatch_setting(
+            path.into_inner(),
+            req.enabled,
+            req.priority.as_deref(),
+            identity.area_scope()?,
+        )
+        .await
+    {
+        Ok(setting) => Ok(HttpResponse::Ok().json(setting)),
+        Err(err) => Err(err),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OptimizeDispatchQuery {
+    area: Option<i32>,
+    weight_by_car_value: Option<bool>,
+}
+
+pub async fn optimize_dispatch_handler(
+    service: web::Data<
+        DispatchService<
+            DispatchRepositoryImpl,
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    identity: AuthenticatedUser,
+    query: web::Query<OptimizeDispatchQuery>,
+) -> Result<HttpResponse, AppError> {
+    match service
+        .optimize_dispatch(
+            query.area,
+            query.weight_by_car_value.unwrap_or(false),
+            identity.area_scope()?,
+        )
+        .await
+    {
+        Ok(plan) => Ok(HttpResponse::Ok().json(plan)),
+        Err(err) => Err(err),
+    }
+}
+
+// 配車した場合の影響を確認する。データベースには何も書き込まない。
+pub async fn preview_dispatch_handler(
+    service: web::Data<
+        DispatchService<
+            DispatchRepositoryImpl,
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    identity: AuthenticatedUser,
+    req: web::Json<PreviewDispatchRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    let assignments: Vec<(i32, i32)> = req
+        .assignments
+        .iter()
+        .map(|assignment| (assignment.order_id, assignment.tow_truck_id))
+        .collect();
+
+    match service
+        .preview_dispatch(
+            req.area_id,
+            &assignments,
+            req.coverage_distance,
+            identity.area_scope()?,
+        )
+        .await
+    {
+        Ok(preview) => Ok(HttpResponse::Ok().json(preview)),
+        Err(err) => Err(err),
+    }
+}
+
+// 確認済みの配車計画を 1 つのトランザクションでまとめて配車する
+pub async fn commit_dispatch_plan_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    identity: AuthenticatedUser,
+    req: web::Json<CommitDispatchPlanRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    let dispatcher_id = identity.dispatcher_id(req.dispatcher_id)?;
+    let assignments: Vec<(i32, i32)> = req
+        .assignments
+        .iter()
+        .map(|assignment| (assignment.order_id, assignment.tow_truck_id))
+        .collect();
+
+    match service
+        .create_dispatcher_orders(dispatcher_id, &assignments, Utc::now())
+        .await
+    {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(err) => Err(err),
+    }
+}
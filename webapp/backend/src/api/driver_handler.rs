@@ -0,0 +1,230 @@
+use crate::domains::dto::driver::{DriverRequestDto, DriverResponseDto};
+use crate::domains::dto::order::OrderDto;
+use crate::domains::order_service::OrderService;
+use crate::domains::tow_truck_service::TowTruckService;
+use crate::errors::AppError;
+use crate::middlewares::authenticated_user::AuthenticatedUser;
+use crate::repositories::auth_repository::AuthRepositoryImpl;
+use crate::repositories::map_repository::MapRepositoryImpl;
+use crate::repositories::order_repository::OrderRepositoryImpl;
+use crate::repositories::tow_truck_repository::TowTruckRepositoryImpl;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_ws::{Message, MessageStream, Session};
+use futures_util::StreamExt;
+use log::warn;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval_at, Duration, Instant};
+
+type DriverTowTruckService =
+    TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>;
+type DriverOrderService = OrderService<
+    OrderRepositoryImpl,
+    TowTruckRepositoryImpl,
+    AuthRepositoryImpl,
+    MapRepositoryImpl,
+>;
+
+// 接続が生きているか確認する間隔
+const DRIVER_HEARTBEAT_SECONDS: u64 = 30;
+// この時間ドライバーから応答がなければ、接続が切れたとみなして閉じる
+const DRIVER_CLIENT_TIMEOUT_SECONDS: u64 = 90;
+
+// ドライバー用の WebSocket。配車の通知を受け取り、位置情報と依頼の状態を送る。
+pub async fn driver_websocket_handler(
+    req: HttpRequest,
+    body: web::Payload,
+    tow_truck_service: web::Data<DriverTowTruckService>,
+    order_service: web::Data<DriverOrderService>,
+    identity: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let tow_truck_id = identity.tow_truck_id(None)?;
+    let (response, session, messages) =
+        actix_ws::handle(&req, body).map_err(|_| AppError::BadRequest)?;
+
+    actix_web::rt::spawn(run_driver_session(
+        tow_truck_service,
+        order_service,
+        tow_truck_id,
+        session,
+        messages,
+    ));
+
+    Ok(response)
+}
+
+async fn run_driver_session(
+    tow_truck_service: web::Data<DriverTowTruckService>,
+    order_service: web::Data<DriverOrderService>,
+    tow_truck_id: i32,
+    mut session: Session,
+    mut messages: MessageStream,
+) {
+    let mut order_events = order_service.subscribe_order_events();
+    let mut heartbeat = interval_at(
+        Instant::now() + Duration::from_secs(DRIVER_HEARTBEAT_SECONDS),
+        Duration::from_secs(DRIVER_HEARTBEAT_SECONDS),
+    );
+    let mut last_heartbeat = Instant::now();
+
+    // 接続する前に割り当てられていた依頼も知らせる
+    if let Some(response) =
+        active_assignment(&tow_truck_service, &order_service, tow_truck_id).await
+    {
+        if send_response(&mut session, &response).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let response = tokio::select! {
+            message = messages.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    last_heartbeat = Instant::now();
+                    handle_driver_request(&tow_truck_service, &order_service, tow_truck_id, &text)
+                        .await
+                }
+                Some(Ok(Message::Ping(bytes))) => {
+                    last_heartbeat = Instant::now();
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                Some(Ok(Message::Pong(_))) => {
+                    last_heartbeat = Instant::now();
+                    continue;
+                }
+                Some(Ok(Message::Close(reason))) => {
+                    let _ = session.close(reason).await;
+                    return;
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => break,
+            },
+            event = order_events.recv() => match event {
+                Ok(order) if is_new_assignment(&order, tow_truck_id) => {
+                    assignment(&tow_truck_service, &order).await
+                }
+                Ok(_) => continue,
+                // 通知を取りこぼした可能性があるため、割り当て済みの依頼を確認し直す
+                Err(RecvError::Lagged(_)) => {
+                    match active_assignment(&tow_truck_service, &order_service, tow_truck_id).await {
+                        Some(response) => response,
+                        None => continue,
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = heartbeat.tick() => {
+                if last_heartbeat.elapsed() > Duration::from_secs(DRIVER_CLIENT_TIMEOUT_SECONDS) {
+                    warn!("レッカー車 {} のドライバーから応答がないため切断します", tow_truck_id);
+                    break;
+                }
+                if session.ping(b"").await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        if send_response(&mut session, &response).await.is_err() {
+            return;
+        }
+    }
+
+    let _ = session.close(None).await;
+}
+
+fn is_new_assignment(order: &OrderDto, tow_truck_id: i32) -> bool {
+    order.tow_truck_id == Some(tow_truck_id) && order.status == "dispatched"
+}
+
+async fn handle_driver_request(
+    tow_truck_service: &DriverTowTruckService,
+    order_service: &DriverOrderService,
+    tow_truck_id: i32,
+    text: &str,
+) -> DriverResponseDto {
+    let request: DriverRequestDto = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(_) => {
+            return DriverResponseDto::Error {
+                message: AppError::BadRequest.to_string(),
+            }
+        }
+    };
+
+    let result = match request {
+        DriverRequestDto::Location { node_id } => {
+            tow_truck_service
+                .update_location(tow_truck_id, node_id)
+                .await
+        }
+        DriverRequestDto::Status { order_id, status } => {
+            order_service
+                .update_order_status(order_id, &status, Some(tow_truck_id), None)
+                .await
+        }
+    };
+
+    match result {
+        Ok(_) => DriverResponseDto::Ack,
+        Err(err) => DriverResponseDto::Error {
+            message: err.to_string(),
+        },
+    }
+}
+
+async fn active_assignment(
+    tow_truck_service: &DriverTowTruckService,
+    order_service: &DriverOrderService,
+    tow_truck_id: i32,
+) -> Option<DriverResponseDto> {
+    match order_service
+        .get_active_order_by_tow_truck_id(tow_truck_id)
+        .await
+    {
+        Ok(Some(order)) => Some(assignment(tow_truck_service, &order).await),
+        Ok(None) => None,
+        Err(e) => {
+            warn!(
+                "レッカー車 {} の割り当て済みの依頼の取得に失敗しました: {:?}",
+                tow_truck_id, e
+            );
+            None
+        }
+    }
+}
+
+async fn assignment(
+    tow_truck_service: &DriverTowTruckService,
+    order: &OrderDto,
+) -> DriverResponseDto {
+    // 経路が求まらなくても、割り当ての通知自体は届ける
+    let route = match order.tow_truck_id {
+        Some(tow_truck_id) => tow_truck_service
+            .get_route_to_node(tow_truck_id, order.node_id)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("依頼 {} までの経路の取得に失敗しました: {:?}", order.id, e);
+                None
+            }),
+        None => None,
+    };
+
+    DriverResponseDto::Assignment {
+        order_id: order.id,
+        node_id: order.node_id,
+        route,
+    }
+}
+
+async fn send_response(
+    session: &mut Session,
+    response: &DriverResponseDto,
+) -> Result<(), actix_ws::Closed> {
+    match serde_json::to_string(response) {
+        Ok(text) => session.text(text).await,
+        Err(_) => Ok(()),
+    }
+}
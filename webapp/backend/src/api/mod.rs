@@ -1,4 +1,6 @@
 pub mod auth_handler;
+pub mod dispatch_handler;
+pub mod driver_handler;
 pub mod health_check_handler;
 pub mod map_handler;
 pub mod order_handler;
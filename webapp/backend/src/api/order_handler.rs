@@ -1,14 +1,26 @@
 use crate::domains::dto::order::{
-    ClientOrderRequestDto, DispatcherOrderRequestDto, UpdateOrderStatusRequestDto,
+    CancelOrderRequestDto, ClientOrderRequestDto, DispatcherOrderRequestDto, OrderDto,
+    OrderEventDto, UpdateOrderStatusRequestDto,
 };
+use crate::domains::dto::tow_truck::TowTruckDto;
 use crate::domains::order_service::OrderService;
+use crate::domains::tow_truck_service::TowTruckService;
 use crate::errors::AppError;
+use crate::middlewares::authenticated_user::AuthenticatedUser;
+use crate::models::order::OrderStatus;
+use crate::models::user::Role;
 use crate::repositories::auth_repository::AuthRepositoryImpl;
 use crate::repositories::map_repository::MapRepositoryImpl;
 use crate::repositories::order_repository::OrderRepositoryImpl;
 use crate::repositories::tow_truck_repository::TowTruckRepositoryImpl;
+use actix_web::http::header;
+use actix_web::web::Bytes;
 use actix_web::{web, HttpResponse};
+use futures_util::stream;
+use log::warn;
 use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{interval_at, Duration, Instant, Interval};
 
 pub async fn update_order_status_handler(
     service: web::Data<
@@ -19,9 +31,24 @@ pub async fn update_order_status_handler(
             MapRepositoryImpl,
         >,
     >,
+    identity: AuthenticatedUser,
     req: web::Json<UpdateOrderStatusRequestDto>,
 ) -> Result<HttpResponse, AppError> {
-    match service.update_order_status(req.order_id, &req.status).await {
+    // ドライバーは自分のレッカー車に割り当てられた依頼しか更新できない
+    let assigned_tow_truck_id = match identity.role() {
+        Some(Role::Driver) => Some(identity.tow_truck_id(None)?),
+        _ => None,
+    };
+
+    match service
+        .update_order_status(
+            req.order_id,
+            &req.status,
+            assigned_tow_truck_id,
+            identity.area_scope()?,
+        )
+        .await
+    {
         Ok(_) => Ok(HttpResponse::Ok().finish()),
         Err(err) => Err(err),
     }
@@ -63,6 +90,7 @@ pub async fn get_paginated_orders_handler(
             MapRepositoryImpl,
         >,
     >,
+    identity: AuthenticatedUser,
     query: web::Query<PaginatedOrderQuery>,
 ) -> Result<HttpResponse, AppError> {
     match service
@@ -73,6 +101,7 @@ pub async fn get_paginated_orders_handler(
             query.sort_order.clone(),
             query.status.clone(),
             query.area,
+            identity.area_scope()?,
         )
         .await
     {
@@ -90,10 +119,13 @@ pub async fn create_client_order_handler(
             MapRepositoryImpl,
         >,
     >,
+    identity: AuthenticatedUser,
     req: web::Json<ClientOrderRequestDto>,
 ) -> Result<HttpResponse, AppError> {
+    let client_id = identity.client_id(req.client_id)?;
+
     match service
-        .create_client_order(req.client_id, req.node_id, req.car_value)
+        .create_client_order(client_id, req.node_id, req.car_value)
         .await
     {
         Ok(_) => Ok(HttpResponse::Created().finish()),
@@ -110,12 +142,15 @@ pub async fn create_dispatcher_order_handler(
             MapRepositoryImpl,
         >,
     >,
+    identity: AuthenticatedUser,
     req: web::Json<DispatcherOrderRequestDto>,
 ) -> Result<HttpResponse, AppError> {
+    let dispatcher_id = identity.dispatcher_id(req.dispatcher_id)?;
+
     match service
         .create_dispatcher_order(
             req.order_id,
-            req.dispatcher_id,
+            dispatcher_id,
             req.tow_truck_id,
             req.order_time,
         )
@@ -125,3 +160,216 @@ pub async fn create_dispatcher_order_handler(
         Err(err) => Err(err),
     }
 }
+
+pub async fn cancel_order_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    identity: AuthenticatedUser,
+    path: web::Path<i32>,
+    req: web::Json<CancelOrderRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    match service
+        .cancel_order(path.into_inner(), &identity.user, &req.reason)
+        .await
+    {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(err) => Err(err),
+    }
+}
+
+// 接続が切れていないことをクライアントとプロキシに伝える間隔
+const ORDER_EVENT_KEEP_ALIVE_SECONDS: u64 = 15;
+
+struct OrderEventStream {
+    order: OrderDto,
+    order_events: broadcast::Receiver<OrderDto>,
+    tow_truck_events: broadcast::Receiver<TowTruckDto>,
+    keep_alive: Interval,
+    // 接続直後は現在の状態を送る
+    initial: bool,
+}
+
+// 依頼の状態、割り当てられたレッカー車、到着予想時間の変化を Server-Sent Events で配信する。
+// 依頼が完了またはキャンセルされたら配信を終える。
+pub async fn stream_order_events_handler(
+    order_service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    tow_truck_service: web::Data<
+        TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
+    >,
+    identity: AuthenticatedUser,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    // 購読してから現在の状態を読むことで、その間の変更を取りこぼさない
+    let order_events = order_service.subscribe_order_events();
+    let tow_truck_events = tow_truck_service.subscribe_tow_truck_events();
+    let order = order_service
+        .get_order_for_user(
+            path.into_inner(),
+            &identity.user,
+            identity.area_scope()?,
+            identity.tow_truck_id,
+        )
+        .await?;
+
+    let state = OrderEventStream {
+        order,
+        order_events,
+        tow_truck_events,
+        keep_alive: interval_at(
+            Instant::now() + Duration::from_secs(ORDER_EVENT_KEEP_ALIVE_SECONDS),
+            Duration::from_secs(ORDER_EVENT_KEEP_ALIVE_SECONDS),
+        ),
+        initial: true,
+    };
+    let events = stream::unfold(Some(state), move |state| {
+        let order_service = order_service.clone();
+        let tow_truck_service = tow_truck_service.clone();
+        async move {
+            let mut state = state?;
+            let message =
+                next_order_event_message(&order_service, &tow_truck_service, &mut state).await?;
+            let next_state = match is_finished(&state.order) {
+                true => None,
+                false => Some(state),
+            };
+            Some((Ok::<_, AppError>(Bytes::from(message)), next_state))
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // nginx にバッファリングさせず、イベントをすぐにクライアントへ流す
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(events))
+}
+
+// 次に送るメッセージを待つ。配信が終了したら None を返す。
+async fn next_order_event_message(
+    order_service: &OrderService<
+        OrderRepositoryImpl,
+        TowTruckRepositoryImpl,
+        AuthRepositoryImpl,
+        MapRepositoryImpl,
+    >,
+    tow_truck_service: &TowTruckService<
+        TowTruckRepositoryImpl,
+        OrderRepositoryImpl,
+        MapRepositoryImpl,
+    >,
+    state: &mut OrderEventStream,
+) -> Option<String> {
+    if state.initial {
+        state.initial = false;
+        let eta_seconds = estimate_order_eta(tow_truck_service, &state.order, None).await;
+        return order_event_message(&state.order, eta_seconds);
+    }
+
+    loop {
+        tokio::select! {
+            event = state.order_events.recv() => match event {
+                Ok(order) if order.id == state.order.id => {
+                    state.order = order;
+                    let eta_seconds = estimate_order_eta(tow_truck_service, &state.order, None).await;
+                    return order_event_message(&state.order, eta_seconds);
+                }
+                // 取りこぼした通知が完了やキャンセルかもしれないため、最新の状態を読み直す
+                Err(RecvError::Lagged(_)) => match order_service.get_order_by_id(state.order.id).await {
+                    Ok(order) => {
+                        state.order = order;
+                        let eta_seconds = estimate_order_eta(tow_truck_service, &state.order, None).await;
+                        return order_event_message(&state.order, eta_seconds);
+                    }
+                    Err(e) => warn!(
+                        "依頼 {} の状態の再取得に失敗しました: {:?}",
+                        state.order.id, e
+                    ),
+                },
+                Ok(_) => {}
+                Err(RecvError::Closed) => return None,
+            },
+            event = state.tow_truck_events.recv() => match event {
+                Ok(tow_truck) if state.order.tow_truck_id == Some(tow_truck.id) => {
+                    let eta_seconds =
+                        estimate_order_eta(tow_truck_service, &state.order, Some(&tow_truck)).await;
+                    return order_event_message(&state.order, eta_seconds);
+                }
+                // 取りこぼした位置情報の代わりに、レッカー車の現在地から到着予想時間を求め直す
+                Err(RecvError::Lagged(_)) if state.order.tow_truck_id.is_some() => {
+                    let eta_seconds = estimate_order_eta(tow_truck_service, &state.order, None).await;
+                    return order_event_message(&state.order, eta_seconds);
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return None,
+            },
+            _ = state.keep_alive.tick() => return Some(": keep-alive\n\n".to_string()),
+        }
+    }
+}
+
+fn order_event_message(order: &OrderDto, eta_seconds: Option<i64>) -> Option<String> {
+    let event = OrderEventDto {
+        order: order.clone(),
+        eta_seconds,
+    };
+    let data = serde_json::to_string(&event).ok()?;
+    Some(format!("event: order\ndata: {}\n\n", data))
+}
+
+fn is_finished(order: &OrderDto) -> bool {
+    matches!(
+        order.status.parse::<OrderStatus>(),
+        Ok(OrderStatus::Completed) | Ok(OrderStatus::Cancelled)
+    )
+}
+
+// レッカー車が依頼の場所へ向かっている間だけ到着予想時間を求める
+async fn estimate_order_eta(
+    tow_truck_service: &TowTruckService<
+        TowTruckRepositoryImpl,
+        OrderRepositoryImpl,
+        MapRepositoryImpl,
+    >,
+    order: &OrderDto,
+    tow_truck: Option<&TowTruckDto>,
+) -> Option<i64> {
+    match order.status.parse::<OrderStatus>() {
+        Ok(OrderStatus::Dispatched) | Ok(OrderStatus::EnRoute) => {}
+        Ok(OrderStatus::Arrived) => return Some(0),
+        _ => return None,
+    }
+
+    let tow_truck = match tow_truck {
+        Some(tow_truck) => tow_truck.clone(),
+        None => match tow_truck_service
+            .get_tow_truck_by_id(order.tow_truck_id?)
+            .await
+        {
+            Ok(Some(tow_truck)) => tow_truck,
+            _ => return None,
+        },
+    };
+    tow_truck_service
+        .estimate_arrival_seconds(tow_truck.area_id, tow_truck.node_id, order.node_id)
+        .await
+        .unwrap_or_else(|e| {
+            warn!(
+                "依頼 {} の到着予想時間の計算に失敗しました: {:?}",
+                order.id, e
+            );
+            None
+        })
+}
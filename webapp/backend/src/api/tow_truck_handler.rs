@@ -1,13 +1,21 @@
 use crate::domains::tow_truck_service::TowTruckService;
 use crate::errors::AppError;
+use crate::middlewares::authenticated_user::AuthenticatedUser;
 use crate::repositories::order_repository::OrderRepositoryImpl;
 use crate::repositories::tow_truck_repository::TowTruckRepositoryImpl;
+use crate::utils::restrict_area;
 use crate::{
-    domains::dto::tow_truck::UpdateLocationRequestDto,
+    domains::dto::tow_truck::{TowTruckDto, UpdateLocationRequestDto},
     repositories::map_repository::MapRepositoryImpl,
 };
+use actix_web::http::header;
+use actix_web::web::Bytes;
 use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures_util::stream;
 use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{interval_at, Duration, Instant, Interval};
 
 #[derive(Deserialize, Debug)]
 pub struct PaginatedTowTruckQuery {
@@ -21,6 +29,7 @@ pub async fn get_paginated_tow_trucks_handler(
     service: web::Data<
         TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
     >,
+    identity: AuthenticatedUser,
     query: web::Query<PaginatedTowTruckQuery>,
 ) -> Result<HttpResponse, AppError> {
     let tow_trucks = service
@@ -29,6 +38,7 @@ pub async fn get_paginated_tow_trucks_handler(
             query.page_size.unwrap_or(-1),
             query.status.clone(),
             query.area,
+            identity.area_scope()?,
         )
         .await?;
 
@@ -49,35 +59,155 @@ pub async fn get_tow_truck_handler(
     }
 }
 
+#[derive(Deserialize, Debug)]
+pub struct LocationHistoryQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    page: Option<i32>,
+    page_size: Option<i32>,
+}
+
+// 一度に返す走行履歴の最大件数
+const MAX_LOCATION_HISTORY_PAGE_SIZE: i32 = 1000;
+
+pub async fn get_location_history_handler(
+    service: web::Data<
+        TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
+    >,
+    identity: AuthenticatedUser,
+    path: web::Path<i32>,
+    query: web::Query<LocationHistoryQuery>,
+) -> Result<HttpResponse, AppError> {
+    let page = query.page.unwrap_or(0);
+    let page_size = query.page_size.unwrap_or(100);
+    if page < 0 || !(1..=MAX_LOCATION_HISTORY_PAGE_SIZE).contains(&page_size) {
+        return Err(AppError::BadRequest);
+    }
+
+    let locations = service
+        .get_location_history(
+            path.into_inner(),
+            query.from,
+            query.to,
+            page,
+            page_size,
+            identity.area_scope()?,
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(locations))
+}
+
 pub async fn update_location_handler(
     service: web::Data<
         TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
     >,
+    identity: AuthenticatedUser,
     req: web::Json<UpdateLocationRequestDto>,
 ) -> Result<HttpResponse, AppError> {
-    service
-        .update_location(req.tow_truck_id, req.node_id)
-        .await?;
+    let tow_truck_id = identity.tow_truck_id(req.tow_truck_id)?;
+    service.update_location(tow_truck_id, req.node_id).await?;
     Ok(HttpResponse::Ok().finish())
 }
 
 #[derive(Deserialize, Debug)]
 pub struct TowTruckQuery {
     order_id: i32,
+    limit: Option<usize>,
+    include_route: Option<bool>,
 }
 
 pub async fn get_nearest_available_tow_trucks_handler(
     service: web::Data<
         TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
     >,
+    identity: AuthenticatedUser,
     query: web::Query<TowTruckQuery>,
 ) -> Result<HttpResponse, AppError> {
-    match service
-        .get_nearest_available_tow_trucks(query.order_id)
-        .await
-    {
-        Ok(Some(tow_truck)) => Ok(HttpResponse::Ok().json(tow_truck)),
-        Ok(None) => Ok(HttpResponse::NotFound().finish()),
-        Err(err) => Err(err),
+    if query.limit == Some(0) {
+        return Err(AppError::BadRequest);
+    }
+
+    let nearest_tow_trucks = service
+        .get_nearest_available_tow_trucks(
+            query.order_id,
+            query.limit.unwrap_or(1),
+            query.include_route.unwrap_or(false),
+            identity.area_scope()?,
+        )
+        .await?;
+
+    // limit を指定しない場合は従来どおり最も近い 1 台だけを返す
+    match query.limit {
+        Some(_) => Ok(HttpResponse::Ok().json(nearest_tow_trucks)),
+        None => match nearest_tow_trucks.into_iter().next() {
+            Some(tow_truck) => Ok(HttpResponse::Ok().json(tow_truck)),
+            None => Ok(HttpResponse::NotFound().finish()),
+        },
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TowTruckStreamQuery {
+    area: Option<i32>,
+}
+
+// 接続が切れていないことをクライアントとプロキシに伝える間隔
+const TOW_TRUCK_STREAM_KEEP_ALIVE_SECONDS: u64 = 15;
+
+// レッカー車の位置・状態が変わるたびに Server-Sent Events で配信する
+pub async fn stream_tow_trucks_handler(
+    service: web::Data<
+        TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
+    >,
+    identity: AuthenticatedUser,
+    query: web::Query<TowTruckStreamQuery>,
+) -> Result<HttpResponse, AppError> {
+    let area = restrict_area(query.area, identity.area_scope()?)?;
+    let receiver = service.subscribe_tow_truck_events();
+    let keep_alive = interval_at(
+        Instant::now() + Duration::from_secs(TOW_TRUCK_STREAM_KEEP_ALIVE_SECONDS),
+        Duration::from_secs(TOW_TRUCK_STREAM_KEEP_ALIVE_SECONDS),
+    );
+
+    let events = stream::unfold(
+        (receiver, keep_alive),
+        move |(mut receiver, mut keep_alive)| async move {
+            let message = next_stream_message(&mut receiver, &mut keep_alive, area).await?;
+            Some((
+                Ok::<_, AppError>(Bytes::from(message)),
+                (receiver, keep_alive),
+            ))
+        },
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // nginx にバッファリングさせず、イベントをすぐにクライアントへ流す
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(events))
+}
+
+// 次に送るメッセージを待つ。配信が終了したら None を返す。
+async fn next_stream_message(
+    receiver: &mut broadcast::Receiver<TowTruckDto>,
+    keep_alive: &mut Interval,
+    area: Option<i32>,
+) -> Option<String> {
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(tow_truck) if area.map_or(true, |area| tow_truck.area_id == area) => {
+                    if let Ok(data) = serde_json::to_string(&tow_truck) {
+                        return Some(format!("event: tow_truck\ndata: {}\n\n", data));
+                    }
+                }
+                // 受信が追いつかなかった分は読み飛ばし、最新の状態から配信を続ける
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return None,
+            },
+            _ = keep_alive.tick() => return Some(": keep-alive\n\n".to_string()),
+        }
     }
 }
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::map_service::MapRepository;
+use crate::errors::AppError;
+use crate::models::graph::Graph;
+
+// エリアごとのグラフをリクエストをまたいで保持する。
+// 初回アクセス時に DB から読み込み、辺の重みが更新されたらその場で書き換える。
+#[derive(Debug, Default)]
+pub struct AreaGraphStore {
+    graphs: RwLock<HashMap<i32, Arc<Mutex<Graph>>>>,
+    // 辺の更新ごとに進める。読み込み中に更新があったグラフは保持しない。
+    generation: AtomicU64,
+}
+
+impl AreaGraphStore {
+    pub fn new() -> Self {
+        AreaGraphStore::default()
+    }
+
+    pub async fn get_or_load<R: MapRepository>(
+        &self,
+        map_repository: &R,
+        area_id: i32,
+    ) -> Result<Arc<Mutex<Graph>>, AppError> {
+        if let Some(graph) = self.graphs.read().unwrap().get(&area_id) {
+            return Ok(graph.clone());
+        }
+
+        let generation = self.generation.load(Ordering::SeqCst);
+
+        let nodes = map_repository.get_all_nodes(Some(area_id)).await?;
+        let edges = map_repository.get_all_edges(Some(area_id)).await?;
+
+        let mut graph = Graph::new();
+        for node in nodes {
+            graph.add_node(node);
+        }
+        for edge in edges {
+            graph.add_edge(edge);
+        }
+        let graph = Arc::new(Mutex::new(graph));
+
+        let mut graphs = self.graphs.write().unwrap();
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return Ok(graph);
+        }
+
+        Ok(graphs.entry(area_id).or_insert(graph).clone())
+    }
+
+    pub fn update_edge(&self, node_a_id: i32, node_b_id: i32, weight: i32) {
+        let graphs = self.graphs.read().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        for graph in graphs.values() {
+            graph
+                .lock()
+                .unwrap()
+                .update_edge_weight(node_a_id, node_b_id, weight);
+        }
+    }
+}
@@ -1,21 +1,45 @@
+use std::fmt::Write;
+use std::fs;
+use std::io::Cursor;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 
-use actix_web::web::Bytes;
-use log::error;
+use actix_web::web;
+use chrono::{DateTime, Duration, Utc};
+use image::{imageops::FilterType, DynamicImage, ImageError, ImageReader};
+use log::{error, warn};
+use sha2::{Digest, Sha256};
 
 use crate::errors::AppError;
-use crate::models::user::{Dispatcher, Session, User};
-use crate::utils::{generate_session_token, hash_password, verify_password};
+use crate::infrastructure::image_store::ImageStore;
+use crate::models::profile_image::{
+    ProfileImageFormat, ProfileImageVariant, MAX_PROFILE_IMAGE_DIMENSION,
+    MIN_PROFILE_IMAGE_DIMENSION,
+};
+use crate::models::user::{Dispatcher, Role, Session, User};
+use crate::utils::{generate_session_token, hash_password, needs_rehash, verify_password};
 
 use super::dto::auth::LoginResponseDto;
+use super::session_cache::SessionCache;
+
+// セッションは最後に利用されてからこの時間で失効する
+const SESSION_TTL_HOURS: i64 = 24;
+// 有効期限の延長は、前回の利用からこの時間が経過したときだけ書き込む
+const SESSION_RENEW_INTERVAL_SECONDS: i64 = 60;
+// 失効したセッションを削除する間隔
+pub const SESSION_SWEEP_INTERVAL_SECONDS: u64 = 600;
+// AuthMiddleware が DB に問い合わせずにセッションを信用する時間と件数の上限
+const SESSION_CACHE_TTL_SECONDS: u64 = 30;
+const SESSION_CACHE_CAPACITY: usize = 10000;
+
+const PROFILE_IMAGE_DIR: &str = "images/CompressionImages2";
+// リサイズしたプロフィール画像の保存先
+const PROFILE_IMAGE_VARIANT_DIR: &str = "images/resized";
 
 pub trait AuthRepository {
     async fn create_user(&self, username: &str, password: &str, role: &str)
         -> Result<(), AppError>;
     async fn find_user_by_id(&self, id: i32) -> Result<Option<User>, AppError>;
     async fn find_user_by_username(&self, username: &str) -> Result<Option<User>, AppError>;
-    async fn create_dispatcher(&self, user_id: i32, area_id: i32) -> Result<(), AppError>;
     async fn find_dispatcher_by_id(&self, id: i32) -> Result<Option<Dispatcher>, AppError>;
     async fn find_dispatcher_by_user_id(
         &self,
@@ -25,9 +49,27 @@ pub trait AuthRepository {
         &self,
         user_id: i32,
     ) -> Result<Option<String>, AppError>;
-    async fn authenticate_user(&self, username: &str, password: &str) -> Result<User, AppError>;
-    async fn create_session(&self, user_id: i32, session_token: &str) -> Result<(), AppError>;
+    async fn update_password(&self, user_id: i32, password: &str) -> Result<(), AppError>;
+    async fn update_profile_image(
+        &self,
+        user_id: i32,
+        profile_image_name: &str,
+    ) -> Result<(), AppError>;
+    async fn create_session(
+        &self,
+        user_id: i32,
+        session_token: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), AppError>;
+    async fn renew_session(
+        &self,
+        session_id: i32,
+        last_seen_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), AppError>;
     async fn delete_session(&self, session_token: &str) -> Result<(), AppError>;
+    async fn delete_sessions_by_user_id(&self, user_id: i32) -> Result<(), AppError>;
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64, AppError>;
     async fn find_session_by_session_token(&self, session_token: &str)
         -> Result<Session, AppError>;
 }
@@ -35,11 +77,22 @@ pub trait AuthRepository {
 #[derive(Debug)]
 pub struct AuthService<T: AuthRepository + std::fmt::Debug> {
     repository: T,
+    session_cache: SessionCache,
+    profile_images: ImageStore,
+    profile_image_variants: ImageStore,
 }
 
 impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
     pub fn new(repository: T) -> Self {
-        AuthService { repository }
+        AuthService {
+            repository,
+            session_cache: SessionCache::new(
+                SESSION_CACHE_CAPACITY,
+                std::time::Duration::from_secs(SESSION_CACHE_TTL_SECONDS),
+            ),
+            profile_images: ImageStore::new(PROFILE_IMAGE_DIR),
+            profile_image_variants: ImageStore::new(PROFILE_IMAGE_VARIANT_DIR),
+        }
     }
 
     pub async fn register_user(
@@ -47,17 +100,18 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
         username: &str,
         password: &str,
         role: &str,
-        area: Option<i32>,
     ) -> Result<LoginResponseDto, AppError> {
-        if role == "dispatcher" && area.is_none() {
-            return Err(AppError::BadRequest);
+        // ディスパッチャーと admin のアカウントは運用側で作成する
+        match role.parse::<Role>()? {
+            Role::Client | Role::Driver => {}
+            Role::Dispatcher | Role::Admin => return Err(AppError::Forbidden),
         }
 
         if (self.repository.find_user_by_username(username).await?).is_some() {
             return Err(AppError::Conflict);
         }
 
-        let hashed_password = hash_password(password).unwrap();
+        let hashed_password = hash_password_blocking(password).await?;
 
         self.repository
             .create_user(username, &hashed_password, role)
@@ -68,36 +122,16 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
         match self.repository.find_user_by_username(username).await? {
             Some(user) => {
                 self.repository
-                    .create_session(user.id, &session_token)
+                    .create_session(user.id, &session_token, session_expires_at())
                     .await?;
-                match user.role.as_str() {
-                    "dispatcher" => {
-                        self.repository
-                            .create_dispatcher(user.id, area.unwrap())
-                            .await?;
-                        let dispatcher = self
-                            .repository
-                            .find_dispatcher_by_user_id(user.id)
-                            .await?
-                            .unwrap();
-                        Ok(LoginResponseDto {
-                            user_id: user.id,
-                            username: user.username,
-                            session_token,
-                            role: user.role,
-                            dispatcher_id: Some(dispatcher.id),
-                            area_id: Some(dispatcher.area_id),
-                        })
-                    }
-                    _ => Ok(LoginResponseDto {
-                        user_id: user.id,
-                        username: user.username,
-                        session_token,
-                        role: user.role,
-                        dispatcher_id: None,
-                        area_id: None,
-                    }),
-                }
+                Ok(LoginResponseDto {
+                    user_id: user.id,
+                    username: user.username,
+                    session_token,
+                    role: user.role,
+                    dispatcher_id: None,
+                    area_id: None,
+                })
             }
             None => Err(AppError::InternalServerError),
         }
@@ -110,14 +144,26 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
     ) -> Result<LoginResponseDto, AppError> {
         match self.repository.find_user_by_username(username).await? {
             Some(user) => {
-                let is_password_valid = verify_password(&user.password, password).unwrap();
+                let is_password_valid = verify_password_blocking(&user.password, password).await?;
                 if !is_password_valid {
                     return Err(AppError::Unauthorized);
                 }
 
+                // 旧形式のハッシュはログインに成功した時点で Argon2id に置き換える
+                if needs_rehash(&user.password) {
+                    let rehashed_password = hash_password_blocking(password).await?;
+                    if let Err(e) = self
+                        .repository
+                        .update_password(user.id, &rehashed_password)
+                        .await
+                    {
+                        warn!("パスワードの再ハッシュに失敗しました: {:?}", e);
+                    }
+                }
+
                 let session_token = generate_session_token();
                 self.repository
-                    .create_session(user.id, &session_token)
+                    .create_session(user.id, &session_token, session_expires_at())
                     .await?;
 
                 match user.role.as_str() {
@@ -150,10 +196,27 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
 
     pub async fn logout_user(&self, session_token: &str) -> Result<(), AppError> {
         self.repository.delete_session(session_token).await?;
+        self.session_cache.invalidate(session_token);
+        Ok(())
+    }
+
+    pub async fn revoke_all_sessions(&self, user_id: i32) -> Result<(), AppError> {
+        self.repository.delete_sessions_by_user_id(user_id).await?;
+        self.session_cache.invalidate_user(user_id);
         Ok(())
     }
 
-    pub async fn get_resized_profile_image_byte(&self, user_id: i32) -> Result<Bytes, AppError> {
+    pub async fn delete_expired_sessions(&self) -> Result<u64, AppError> {
+        self.repository.delete_expired_sessions(Utc::now()).await
+    }
+
+    // 指定サイズ・形式に変換したプロフィール画像のパスを返す。
+    // 変換結果はディスクに保存し、元画像が更新されていなければ再利用する。
+    pub async fn get_resized_profile_image_path(
+        &self,
+        user_id: i32,
+        variant: ProfileImageVariant,
+    ) -> Result<PathBuf, AppError> {
         let profile_image_name = match self
             .repository
             .find_profile_image_name_by_user_id(user_id)
@@ -164,38 +227,235 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
             Err(_) => return Err(AppError::NotFound),
         };
 
-        let path: PathBuf =
-            Path::new(&format!("images/CompressionImages2/{}", profile_image_name)).to_path_buf();
-
-        let output = Command::new("magick")
-            .arg(&path)
-            .arg("-resize")
-            .arg("500x500")
-            .arg("png:-")
-            .output()
-            .map_err(|e| {
-                error!("画像リサイズのコマンド実行に失敗しました: {:?}", e);
-                AppError::InternalServerError
-            })?;
-
-        match output.status.success() {
-            true => Ok(Bytes::from(output.stdout)),
-            false => {
-                error!(
-                    "画像リサイズのコマンド実行に失敗しました: {:?}",
-                    String::from_utf8_lossy(&output.stderr)
-                );
-                Err(AppError::InternalServerError)
+        self.resized_profile_image_path(profile_image_name, variant)
+            .await
+    }
+
+    // アップロードされた画像を保存し、ユーザーのプロフィール画像を差し替える。
+    // ファイル名は内容のハッシュから決めるため、同じ画像は一度しか保存されない。
+    pub async fn update_profile_image(
+        &self,
+        user_id: i32,
+        format: ProfileImageFormat,
+        image_byte: Vec<u8>,
+    ) -> Result<String, AppError> {
+        let profile_images = self.profile_images.clone();
+        let profile_image_name =
+            web::block(move || store_profile_image(&profile_images, format, &image_byte))
+                .await
+                .map_err(|e| {
+                    error!("画像の保存の実行に失敗しました: {:?}", e);
+                    AppError::InternalServerError
+                })??;
+
+        self.repository
+            .update_profile_image(user_id, &profile_image_name)
+            .await?;
+        // キャッシュ中のユーザー情報は古いプロフィール画像を指している
+        self.session_cache.invalidate_user(user_id);
+
+        // 初回の配信で変換を待たせないよう、既定のサイズを先に生成しておく
+        if let Err(e) = self
+            .resized_profile_image_path(profile_image_name.clone(), ProfileImageVariant::default())
+            .await
+        {
+            warn!("プロフィール画像の事前変換に失敗しました: {:?}", e);
+        }
+
+        Ok(profile_image_name)
+    }
+
+    async fn resized_profile_image_path(
+        &self,
+        profile_image_name: String,
+        variant: ProfileImageVariant,
+    ) -> Result<PathBuf, AppError> {
+        let profile_images = self.profile_images.clone();
+        let profile_image_variants = self.profile_image_variants.clone();
+
+        web::block(move || {
+            let source_path = profile_images.resolve(&profile_image_name)?;
+            let variant_name = variant.file_name(&profile_image_name);
+            if let Ok(variant_path) = profile_image_variants.resolve(&variant_name) {
+                if is_variant_fresh(&source_path, &variant_path) {
+                    return Ok(variant_path);
+                }
             }
+            let resized_image_byte = resize_profile_image(&source_path, variant)?;
+            profile_image_variants.write(&variant_name, &resized_image_byte)
+        })
+        .await
+        .map_err(|e| {
+            error!("画像リサイズの実行に失敗しました: {:?}", e);
+            AppError::InternalServerError
+        })?
+    }
+
+    pub async fn get_dispatcher_by_user_id(
+        &self,
+        user_id: i32,
+    ) -> Result<Option<Dispatcher>, AppError> {
+        self.repository.find_dispatcher_by_user_id(user_id).await
+    }
+
+    // セッショントークンからユーザーを解決する。キャッシュにあれば DB には問い合わせない。
+    pub async fn authenticate(&self, session_token: &str) -> Result<User, AppError> {
+        if let Some((_, user)) = self.session_cache.get(session_token) {
+            return Ok(user);
+        }
+
+        let mut session = self.validate_session(session_token).await?;
+
+        // 利用されるたびに有効期限を延長する
+        match self.renew_session(&session).await {
+            Ok(Some(renewed_session)) => session = renewed_session,
+            Ok(None) => {}
+            Err(e) => warn!("セッションの有効期限の延長に失敗しました: {:?}", e),
         }
+
+        let user = self.find_session_user(&session).await?;
+        self.session_cache.insert(session, user.clone());
+
+        Ok(user)
     }
 
-    pub async fn validate_session(&self, session_token: &str) -> Result<bool, AppError> {
+    pub async fn validate_session(&self, session_token: &str) -> Result<Session, AppError> {
         let session = self
             .repository
             .find_session_by_session_token(session_token)
+            .await
+            .map_err(|_| AppError::Unauthorized)?;
+        if !session.is_valid || session.expires_at <= Utc::now() {
+            return Err(AppError::Unauthorized);
+        }
+
+        Ok(session)
+    }
+
+    pub async fn find_session_user(&self, session: &Session) -> Result<User, AppError> {
+        self.repository
+            .find_user_by_id(session.user_id)
+            .await?
+            .ok_or(AppError::Unauthorized)
+    }
+
+    // 有効期限を延長した場合は、延長後のセッションを返す
+    pub async fn renew_session(&self, session: &Session) -> Result<Option<Session>, AppError> {
+        let now = Utc::now();
+        if now - session.last_seen_at < Duration::seconds(SESSION_RENEW_INTERVAL_SECONDS) {
+            return Ok(None);
+        }
+
+        let expires_at = session_expires_at();
+        self.repository
+            .renew_session(session.id, now, expires_at)
             .await?;
 
-        Ok(session.is_valid)
+        Ok(Some(Session {
+            last_seen_at: now,
+            expires_at,
+            ..session.clone()
+        }))
+    }
+}
+
+// Argon2 は計算が重いため、ワーカースレッドを止めないようにブロッキング用のスレッドで実行する
+async fn hash_password_blocking(password: &str) -> Result<String, AppError> {
+    let password = password.to_string();
+    web::block(move || hash_password(&password))
+        .await
+        .map_err(|e| {
+            error!("パスワードのハッシュ化の実行に失敗しました: {:?}", e);
+            AppError::InternalServerError
+        })?
+}
+
+async fn verify_password_blocking(
+    hashed_password: &str,
+    input_password: &str,
+) -> Result<bool, AppError> {
+    let hashed_password = hashed_password.to_string();
+    let input_password = input_password.to_string();
+    web::block(move || verify_password(&hashed_password, &input_password))
+        .await
+        .map_err(|e| {
+            error!("パスワードの検証の実行に失敗しました: {:?}", e);
+            AppError::InternalServerError
+        })?
+}
+
+fn session_expires_at() -> DateTime<Utc> {
+    Utc::now() + Duration::hours(SESSION_TTL_HOURS)
+}
+
+fn is_variant_fresh(source_path: &Path, variant_path: &Path) -> bool {
+    let source_modified = fs::metadata(source_path).and_then(|m| m.modified());
+    let variant_modified = fs::metadata(variant_path).and_then(|m| m.modified());
+    match (source_modified, variant_modified) {
+        (Ok(source_modified), Ok(variant_modified)) => variant_modified >= source_modified,
+        _ => false,
+    }
+}
+
+fn resize_profile_image(
+    source_path: &Path,
+    variant: ProfileImageVariant,
+) -> Result<Vec<u8>, AppError> {
+    let source_image = image::open(source_path).map_err(|e| match e {
+        ImageError::IoError(_) => AppError::NotFound,
+        e => {
+            error!("画像の読み込みに失敗しました: {:?}", e);
+            AppError::InternalServerError
+        }
+    })?;
+
+    let resized_image = source_image.resize(variant.width, variant.height, FilterType::Lanczos3);
+    let resized_image = match variant.format {
+        // JPEG はアルファチャンネルを持てない
+        ProfileImageFormat::Jpeg => DynamicImage::ImageRgb8(resized_image.to_rgb8()),
+        _ => DynamicImage::ImageRgba8(resized_image.to_rgba8()),
+    };
+
+    let mut encoded = Vec::new();
+    resized_image
+        .write_to(
+            &mut Cursor::new(&mut encoded),
+            variant.format.image_format(),
+        )
+        .map_err(|e| {
+            error!("画像のエンコードに失敗しました: {:?}", e);
+            AppError::InternalServerError
+        })?;
+
+    Ok(encoded)
+}
+
+fn store_profile_image(
+    profile_images: &ImageStore,
+    format: ProfileImageFormat,
+    image_byte: &[u8],
+) -> Result<String, AppError> {
+    // 展開前に画像サイズを確認し、巨大な画像をデコードしないようにする
+    let (width, height) = ImageReader::with_format(Cursor::new(image_byte), format.image_format())
+        .into_dimensions()
+        .map_err(|_| AppError::BadRequest)?;
+    let allowed_dimension = MIN_PROFILE_IMAGE_DIMENSION..=MAX_PROFILE_IMAGE_DIMENSION;
+    if !allowed_dimension.contains(&width) || !allowed_dimension.contains(&height) {
+        return Err(AppError::BadRequest);
+    }
+    image::load_from_memory_with_format(image_byte, format.image_format())
+        .map_err(|_| AppError::BadRequest)?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(image_byte);
+    let mut profile_image_name = String::new();
+    for byte in hasher.finalize() {
+        write!(&mut profile_image_name, "{:02x}", byte).unwrap();
+    }
+    write!(&mut profile_image_name, ".{}", format.extension()).unwrap();
+
+    if profile_images.resolve(&profile_image_name).is_err() {
+        profile_images.write(&profile_image_name, image_byte)?;
     }
+    Ok(profile_image_name)
 }
@@ -0,0 +1,581 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use log::{error, info, warn};
+
+use super::area_graph_store::AreaGraphStore;
+use super::auth_service::AuthRepository;
+use super::dto::dispatch::{
+    AutoDispatchSettingDto, DispatchAssignmentDto, DispatchPlanDto, DispatchPreviewDto,
+};
+use super::map_service::MapRepository;
+use super::order_service::{OrderRepository, OrderService};
+use super::tow_truck_service::TowTruckRepository;
+use crate::errors::AppError;
+use crate::models::assignment::solve_assignment;
+use crate::models::dispatch::{AutoDispatchSetting, DispatchPriority};
+use crate::models::graph::{travel_seconds, MAX_DISPATCH_DISTANCE};
+use crate::models::tow_truck::TowTruck;
+use crate::utils::restrict_area;
+
+// 自動配車を行う間隔
+pub const AUTO_DISPATCH_INTERVAL_SECONDS: u64 = 10;
+// 1 回の自動配車で 1 エリアあたりに扱う依頼の上限
+const AUTO_DISPATCH_BATCH_SIZE: i32 = 100;
+// 自動配車に失敗した依頼とレッカー車の組を、再び試すまでの間隔
+const AUTO_DISPATCH_RETRY_SECONDS: u64 = 60;
+// 一括配車の最適化で扱う依頼の上限。計算量は依頼数の 2 乗とレッカー車の数に比例する。
+const OPTIMIZE_MAX_ORDERS: i32 = 200;
+// 配車のプレビューで影響を調べる対応待ちの依頼の上限
+const PREVIEW_MAX_PENDING_ORDERS: i32 = 1000;
+
+pub trait DispatchRepository {
+    async fn find_auto_dispatch_setting(
+        &self,
+        area_id: i32,
+    ) -> Result<Option<AutoDispatchSetting>, AppError>;
+    async fn get_enabled_auto_dispatch_settings(
+        &self,
+    ) -> Result<Vec<AutoDispatchSetting>, AppError>;
+    async fn upsert_auto_dispatch_setting(
+        &self,
+        area_id: i32,
+        enabled: bool,
+        priority: &str,
+    ) -> Result<(), AppError>;
+    // 自動配車の記録に使う、エリアのシステムディスパッチャー
+    async fn find_system_dispatcher_id(&self, area_id: i32) -> Result<Option<i32>, AppError>;
+}
+
+#[derive(Debug)]
+pub struct DispatchService<
+    T: DispatchRepository + std::fmt::Debug,
+    U: OrderRepository + std::fmt::Debug,
+    V: TowTruckRepository + std::fmt::Debug,
+    W: MapRepository + std::fmt::Debug,
+> {
+    dispatch_repository: T,
+    order_repository: U,
+    tow_truck_repository: V,
+    map_repository: W,
+    area_graph_store: Arc<AreaGraphStore>,
+    // 自動配車に失敗した (order_id, tow_truck_id) と失敗した時刻
+    failed_auto_dispatches: Mutex<HashMap<(i32, i32), Instant>>,
+}
+
+impl<
+        T: DispatchRepository + std::fmt::Debug,
+        U: OrderRepository + std::fmt::Debug,
+        V: TowTruckRepository + std::fmt::Debug,
+        W: MapRepository + std::fmt::Debug,
+    > DispatchService<T, U, V, W>
+{
+    pub fn new(
+        dispatch_repository: T,
+        order_repository: U,
+        tow_truck_repository: V,
+        map_repository: W,
+        area_graph_store: Arc<AreaGraphStore>,
+    ) -> Self {
+        DispatchService {
+            dispatch_repository,
+            order_repository,
+            tow_truck_repository,
+            map_repository,
+            area_graph_store,
+            failed_auto_dispatches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn get_auto_dispatch_setting(
+        &self,
+        area_id: i32,
+        dispatcher_area_id: Option<i32>,
+    ) -> Result<AutoDispatchSettingDto, AppError> {
+        restrict_area(Some(area_id), dispatcher_area_id)?;
+        let setting = self
+            .dispatch_repository
+            .find_auto_dispatch_setting(area_id)
+            .await?
+            .unwrap_or(AutoDispatchSetting {
+                area_id,
+                enabled: false,
+                priority: DispatchPriority::OrderTime.as_str().to_string(),
+            });
+
+        Ok(AutoDispatchSettingDto::from_entity(setting))
+    }
+
+    pub async fn update_auto_dispatch_setting(
+        &self,
+        area_id: i32,
+        enabled: bool,
+        priority: Option<&str>,
+        dispatcher_area_id: Option<i32>,
+    ) -> Result<AutoDispatchSettingDto, AppError> {
+        restrict_area(Some(area_id), dispatcher_area_id)?;
+        let priority: DispatchPriority = priority.unwrap_or("order_time").parse()?;
+        self.dispatch_repository
+            .upsert_auto_dispatch_setting(area_id, enabled, priority.as_str())
+            .await?;
+
+        Ok(AutoDispatchSettingDto {
+            area_id,
+            enabled,
+            priority: priority.as_str().to_string(),
+        })
+    }
+
+    // 自動配車が有効なエリアの対応待ちの依頼に、最も近い対応可能なレッカー車を割り当てる。
+    // 配車自体はディスパッチャーが手配する場合と同じく OrderService に任せる。
+    pub async fn run_auto_dispatch<OT, OU, OV, OW>(
+        &self,
+        order_service: &OrderService<OT, OU, OV, OW>,
+    ) -> Result<usize, AppError>
+    where
+        OT: OrderRepository + std::fmt::Debug,
+        OU: TowTruckRepository + std::fmt::Debug,
+        OV: AuthRepository + std::fmt::Debug,
+        OW: MapRepository + std::fmt::Debug,
+    {
+        let settings = self
+            .dispatch_repository
+            .get_enabled_auto_dispatch_settings()
+            .await?;
+
+        // 再試行の間隔を過ぎた失敗は忘れる
+        let retry_interval = Duration::from_secs(AUTO_DISPATCH_RETRY_SECONDS);
+        self.failed_auto_dispatches
+            .lock()
+            .unwrap()
+            .retain(|_, failed_at| failed_at.elapsed() < retry_interval);
+
+        let mut dispatched_count = 0;
+        for setting in settings {
+            let priority: DispatchPriority = match setting.priority.parse() {
+                Ok(priority) => priority,
+                Err(_) => {
+                    warn!(
+                        "エリア {} の自動配車の優先順位 {} が不正です",
+                        setting.area_id, setting.priority
+                    );
+                    continue;
+                }
+            };
+            // 1 つのエリアで失敗しても、残りのエリアの自動配車は続ける
+            let dispatcher_id = match self
+                .dispatch_repository
+                .find_system_dispatcher_id(setting.area_id)
+                .await
+            {
+                Ok(Some(dispatcher_id)) => dispatcher_id,
+                Ok(None) => {
+                    warn!(
+                        "エリア {} のシステムディスパッチャーが存在しないため自動配車できません",
+                        setting.area_id
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    error!(
+                        "エリア {} のシステムディスパッチャーの取得に失敗しました: {:?}",
+                        setting.area_id, e
+                    );
+                    continue;
+                }
+            };
+
+            let assignments = match self.plan_auto_dispatch(setting.area_id, priority).await {
+                Ok(assignments) => assignments,
+                Err(e) => {
+                    error!(
+                        "エリア {} の自動配車の計画に失敗しました: {:?}",
+                        setting.area_id, e
+                    );
+                    continue;
+                }
+            };
+            for (order_id, tow_truck_id) in assignments {
+                // 直前に失敗した組は、再試行の間隔を過ぎるまで試さない
+                if self
+                    .failed_auto_dispatches
+                    .lock()
+                    .unwrap()
+                    .contains_key(&(order_id, tow_truck_id))
+                {
+                    continue;
+                }
+
+                // ディスパッチャーが先に手配した依頼やレッカー車は Conflict になる
+                match order_service
+                    .create_dispatcher_order(order_id, dispatcher_id, tow_truck_id, Utc::now())
+                    .await
+                {
+                    Ok(_) => dispatched_count += 1,
+                    Err(e) => {
+                        warn!(
+                            "依頼 {} へのレッカー車 {} の自動配車に失敗しました: {:?}",
+                            order_id, tow_truck_id, e
+                        );
+                        self.failed_auto_dispatches
+                            .lock()
+                            .unwrap()
+                            .insert((order_id, tow_truck_id), Instant::now());
+                    }
+                }
+            }
+        }
+
+        if dispatched_count > 0 {
+            info!("{} 件の依頼を自動配車しました", dispatched_count);
+        }
+        Ok(dispatched_count)
+    }
+
+    // 優先順位の高い依頼から順に、残っているレッカー車のうち最も近いものを割り当てる
+    async fn plan_auto_dispatch(
+        &self,
+        area_id: i32,
+        priority: DispatchPriority,
+    ) -> Result<Vec<(i32, i32)>, AppError> {
+        let orders = self
+            .order_repository
+            .get_paginated_orders_with_details(
+                0,
+                AUTO_DISPATCH_BATCH_SIZE,
+                Some(priority.as_str().to_string()),
+                Some(priority.sort_order().to_string()),
+                Some("pending".to_string()),
+                Some(area_id),
+            )
+            .await?;
+        let tow_trucks = self
+            .tow_truck_repository
+            .get_paginated_tow_trucks(0, -1, Some("available".to_string()), Some(area_id))
+            .await?;
+        if orders.is_empty() || tow_trucks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let graph = self
+            .area_graph_store
+            .get_or_load(&self.map_repository, area_id)
+            .await?;
+        let mut graph = graph.lock().unwrap();
+
+        // 同じノードにいるレッカー車は ID の小さい順に割り当てる
+        let mut tow_truck_ids_by_node_id: HashMap<i32, Vec<i32>> = HashMap::new();
+        for tow_truck in tow_trucks {
+            tow_truck_ids_by_node_id
+                .entry(tow_truck.node_id)
+                .or_default()
+                .push(tow_truck.id);
+        }
+        for tow_truck_ids in tow_truck_ids_by_node_id.values_mut() {
+            tow_truck_ids.sort_unstable_by(|a, b| b.cmp(a));
+        }
+        let mut tow_truck_node_ids: HashSet<i32> =
+            tow_truck_ids_by_node_id.keys().copied().collect();
+
+        let mut assignments = Vec::new();
+        for order in orders {
+            if tow_truck_node_ids.is_empty() {
+                break;
+            }
+
+            let nearest_node_id = graph
+                .nearest_targets(order.node_id, &tow_truck_node_ids, 1)
+                .into_iter()
+                .filter(|(_, distance)| *distance <= MAX_DISPATCH_DISTANCE)
+                .min_by_key(|(node_id, distance)| {
+                    (*distance, tow_truck_ids_by_node_id[node_id].last().copied())
+                })
+                .map(|(node_id, _)| node_id);
+            let nearest_node_id = match nearest_node_id {
+                Some(node_id) => node_id,
+                None => continue,
+            };
+
+            let tow_truck_ids = tow_truck_ids_by_node_id.get_mut(&nearest_node_id).unwrap();
+            if let Some(tow_truck_id) = tow_truck_ids.pop() {
+                assignments.push((order.id, tow_truck_id));
+            }
+            if tow_truck_ids.is_empty() {
+                tow_truck_node_ids.remove(&nearest_node_id);
+            }
+        }
+
+        Ok(assignments)
+    }
+
+    // エリアの対応待ちの依頼と対応可能なレッカー車の組み合わせのうち、移動距離の総和が最小になる
+    // 割り当てを求める。weight_by_car_value のときは車の価値が高い依頼ほど距離を重く見積もる。
+    // 結果は提案であり、配車はしない。
+    pub async fn optimize_dispatch(
+        &self,
+        area_id: Option<i32>,
+        weight_by_car_value: bool,
+        dispatcher_area_id: Option<i32>,
+    ) -> Result<DispatchPlanDto, AppError> {
+        let area_id = restrict_area(area_id, dispatcher_area_id)?.ok_or(AppError::BadRequest)?;
+        let orders = self
+            .order_repository
+            .get_paginated_orders_with_details(
+                0,
+                OPTIMIZE_MAX_ORDERS,
+                Some(DispatchPriority::OrderTime.as_str().to_string()),
+                Some(DispatchPriority::OrderTime.sort_order().to_string()),
+                Some("pending".to_string()),
+                Some(area_id),
+            )
+            .await?;
+        let tow_trucks = self
+            .tow_truck_repository
+            .get_paginated_tow_trucks(0, -1, Some("available".to_string()), Some(area_id))
+            .await?;
+
+        // 依頼ごとにレッカー車のいるノードまでの距離を求める
+        let distances: Vec<HashMap<i32, i32>> = {
+            let graph = self
+                .area_graph_store
+                .get_or_load(&self.map_repository, area_id)
+                .await?;
+            let mut graph = graph.lock().unwrap();
+            let tow_truck_node_ids: HashSet<i32> = tow_trucks
+                .iter()
+                .map(|tow_truck| tow_truck.node_id)
+                .collect();
+            orders
+                .iter()
+                .map(|order| {
+                    graph
+                        .nearest_targets(
+                            order.node_id,
+                            &tow_truck_node_ids,
+                            tow_truck_node_ids.len(),
+                        )
+                        .into_iter()
+                        .filter(|(_, distance)| *distance <= MAX_DISPATCH_DISTANCE)
+                        .collect()
+                })
+                .collect()
+        };
+
+        // 重みは 1 〜 2 の範囲に収め、価値の低い依頼でも距離が無視されないようにする
+        let max_car_value = orders
+            .iter()
+            .map(|order| order.car_value)
+            .fold(0.0, f64::max);
+        let weights: Vec<f64> = orders
+            .iter()
+            .map(|order| match weight_by_car_value && max_car_value > 0.0 {
+                true => 1.0 + order.car_value / max_car_value,
+                false => 1.0,
+            })
+            .collect();
+
+        // レッカー車の列の後ろに、依頼を割り当てないことを表す列を依頼の数だけ並べる。
+        // 割り当てない場合のコストはどの移動距離よりも大きくし、できるだけ多くの依頼に配車させる。
+        let costs: Vec<Vec<i64>> = orders
+            .iter()
+            .enumerate()
+            .map(|(i, _)| {
+                let unassigned_cost =
+                    ((MAX_DISPATCH_DISTANCE as f64 + 1.0) * weights[i]).ceil() as i64;
+                tow_trucks
+                    .iter()
+                    .map(|tow_truck| match distances[i].get(&tow_truck.node_id) {
+                        Some(&distance) => (distance as f64 * weights[i]).round() as i64,
+                        None => unassigned_cost + 1,
+                    })
+                    .chain(std::iter::repeat(unassigned_cost).take(orders.len()))
+                    .collect()
+            })
+            .collect();
+        // 列はレッカー車の数 + 依頼の数あるため、行数が列数を超えることはない
+        let solution = solve_assignment(&costs);
+
+        let mut assignments = Vec::new();
+        let mut unassigned_order_ids = Vec::new();
+        for (i, order) in orders.iter().enumerate() {
+            let assigned = tow_trucks.get(solution[i]).and_then(|tow_truck| {
+                distances[i]
+                    .get(&tow_truck.node_id)
+                    .map(|&distance| (tow_truck, distance))
+            });
+            match assigned {
+                Some((tow_truck, distance)) => assignments.push(DispatchAssignmentDto {
+                    order_id: order.id,
+                    tow_truck_id: tow_truck.id,
+                    distance,
+                    eta_seconds: travel_seconds(distance),
+                    car_value: order.car_value,
+                }),
+                None => unassigned_order_ids.push(order.id),
+            }
+        }
+        let total_distance = assignments
+            .iter()
+            .map(|assignment| assignment.distance as i64)
+            .sum();
+
+        Ok(DispatchPlanDto {
+            area_id,
+            assignments,
+            unassigned_order_ids,
+            total_distance,
+        })
+    }
+
+    // 仮の配車を行った場合の移動距離と、残りのレッカー車で対応待ちの依頼やエリアを
+    // どれだけカバーできるかを求める。データベースには何も書き込まない。
+    pub async fn preview_dispatch(
+        &self,
+        area_id: Option<i32>,
+        assignments: &[(i32, i32)],
+        coverage_distance: i32,
+        dispatcher_area_id: Option<i32>,
+    ) -> Result<DispatchPreviewDto, AppError> {
+        let area_id = restrict_area(area_id, dispatcher_area_id)?.ok_or(AppError::BadRequest)?;
+        if !(0..=MAX_DISPATCH_DISTANCE).contains(&coverage_distance) {
+            return Err(AppError::BadRequest);
+        }
+        let order_ids: HashSet<i32> = assignments.iter().map(|(order_id, _)| *order_id).collect();
+        let tow_truck_ids: HashSet<i32> = assignments
+            .iter()
+            .map(|(_, tow_truck_id)| *tow_truck_id)
+            .collect();
+        if order_ids.len() != assignments.len() || tow_truck_ids.len() != assignments.len() {
+            return Err(AppError::BadRequest);
+        }
+
+        let pending_orders = self
+            .order_repository
+            .get_paginated_orders_with_details(
+                0,
+                PREVIEW_MAX_PENDING_ORDERS,
+                Some(DispatchPriority::OrderTime.as_str().to_string()),
+                Some(DispatchPriority::OrderTime.sort_order().to_string()),
+                Some("pending".to_string()),
+                Some(area_id),
+            )
+            .await?;
+        let tow_trucks = self
+            .tow_truck_repository
+            .get_paginated_tow_trucks(0, -1, Some("available".to_string()), Some(area_id))
+            .await?;
+
+        // 配車と同じ条件で、依頼とレッカー車が手配できる状態かを確かめる
+        let mut assigned = Vec::with_capacity(assignments.len());
+        for &(order_id, tow_truck_id) in assignments {
+            let order = self.order_repository.find_order_by_id(order_id).await?;
+            let order_area_id = self
+                .map_repository
+                .get_area_id_by_node_id(order.node_id)
+                .await?;
+            if order_area_id != area_id {
+                return Err(AppError::BadRequest);
+            }
+            if order.status != "pending" {
+                return Err(AppError::Conflict);
+            }
+
+            let tow_truck = self
+                .tow_truck_repository
+                .find_tow_truck_by_id(tow_truck_id)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            if tow_truck.area_id != area_id {
+                return Err(AppError::BadRequest);
+            }
+            if tow_truck.status != "available" {
+                return Err(AppError::Conflict);
+            }
+            assigned.push((order, tow_truck));
+        }
+
+        let graph = self
+            .area_graph_store
+            .get_or_load(&self.map_repository, area_id)
+            .await?;
+        let mut graph = graph.lock().unwrap();
+
+        let mut assignment_dtos = Vec::with_capacity(assigned.len());
+        for (order, tow_truck) in &assigned {
+            let distance = graph.shortest_path(tow_truck.node_id, order.node_id);
+            if distance > MAX_DISPATCH_DISTANCE {
+                return Err(AppError::BadRequest);
+            }
+            assignment_dtos.push(DispatchAssignmentDto {
+                order_id: order.id,
+                tow_truck_id: tow_truck.id,
+                distance,
+                eta_seconds: travel_seconds(distance),
+                car_value: order.car_value,
+            });
+        }
+        let total_distance = assignment_dtos
+            .iter()
+            .map(|assignment| assignment.distance as i64)
+            .sum();
+
+        // 無向グラフなので、レッカー車から広げた距離がそのままノードからレッカー車までの距離になる
+        let node_ids_before: HashSet<i32> = tow_trucks
+            .iter()
+            .map(|tow_truck| tow_truck.node_id)
+            .collect();
+        let remaining_tow_trucks: Vec<&TowTruck> = tow_trucks
+            .iter()
+            .filter(|tow_truck| !tow_truck_ids.contains(&tow_truck.id))
+            .collect();
+        let node_ids_after: HashSet<i32> = remaining_tow_trucks
+            .iter()
+            .map(|tow_truck| tow_truck.node_id)
+            .collect();
+        let covered_before = graph.distances_within(&node_ids_before, coverage_distance);
+        let covered_after = graph.distances_within(&node_ids_after, coverage_distance);
+
+        let mut uncovered_order_ids = Vec::new();
+        let mut newly_uncovered_order_ids = Vec::new();
+        for order in pending_orders
+            .iter()
+            .filter(|order| !order_ids.contains(&order.id))
+        {
+            if covered_after.contains_key(&order.node_id) {
+                continue;
+            }
+            uncovered_order_ids.push(order.id);
+            if covered_before.contains_key(&order.node_id) {
+                newly_uncovered_order_ids.push(order.id);
+            }
+        }
+
+        let coverage = |covered: &HashMap<i32, i32>| match graph.nodes.len() {
+            0 => 0.0,
+            node_count => {
+                let covered_count = graph
+                    .nodes
+                    .keys()
+                    .filter(|node_id| covered.contains_key(node_id))
+                    .count();
+                covered_count as f64 / node_count as f64
+            }
+        };
+        let coverage_before = coverage(&covered_before);
+        let coverage_after = coverage(&covered_after);
+
+        Ok(DispatchPreviewDto {
+            area_id,
+            coverage_distance,
+            assignments: assignment_dtos,
+            total_distance,
+            uncovered_order_ids,
+            newly_uncovered_order_ids,
+            available_tow_truck_count_after: remaining_tow_trucks.len(),
+            coverage_before,
+            coverage_after,
+        })
+    }
+}
@@ -7,7 +7,6 @@ pub struct RegisterRequestDto {
     pub username: String,
     pub password: String,
     pub role: String,
-    pub area_id: Option<i32>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -21,6 +20,13 @@ pub struct LogoutRequestDto {
     pub session_token: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ProfileImageQueryDto {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub format: Option<String>,
+}
+
 // Output Data Structure
 
 #[derive(Serialize)]
@@ -32,3 +38,8 @@ pub struct LoginResponseDto {
     pub dispatcher_id: Option<i32>,
     pub area_id: Option<i32>,
 }
+
+#[derive(Serialize)]
+pub struct ProfileImageResponseDto {
+    pub profile_image: String,
+}
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+// Input Data Structure
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateAutoDispatchRequestDto {
+    pub enabled: bool,
+    pub priority: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DispatchAssignmentRequestDto {
+    pub order_id: i32,
+    pub tow_truck_id: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PreviewDispatchRequestDto {
+    pub area_id: Option<i32>,
+    // この距離以内にレッカー車がいれば、依頼やノードが対応可能な範囲にあるとみなす
+    pub coverage_distance: i32,
+    pub assignments: Vec<DispatchAssignmentRequestDto>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CommitDispatchPlanRequestDto {
+    pub dispatcher_id: Option<i32>,
+    pub assignments: Vec<DispatchAssignmentRequestDto>,
+}
+
+// Output Data Structure
+
+#[derive(Serialize)]
+pub struct AutoDispatchSettingDto {
+    pub area_id: i32,
+    pub enabled: bool,
+    pub priority: String,
+}
+
+impl AutoDispatchSettingDto {
+    pub fn from_entity(entity: crate::models::dispatch::AutoDispatchSetting) -> Self {
+        AutoDispatchSettingDto {
+            area_id: entity.area_id,
+            enabled: entity.enabled,
+            priority: entity.priority,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DispatchAssignmentDto {
+    pub order_id: i32,
+    pub tow_truck_id: i32,
+    pub distance: i32,
+    pub eta_seconds: i64,
+    pub car_value: f64,
+}
+
+#[derive(Serialize)]
+pub struct DispatchPlanDto {
+    pub area_id: i32,
+    pub assignments: Vec<DispatchAssignmentDto>,
+    // レッカー車が足りない、または辿り着けないため割り当てなかった依頼
+    pub unassigned_order_ids: Vec<i32>,
+    pub total_distance: i64,
+}
+
+#[derive(Serialize)]
+pub struct DispatchPreviewDto {
+    pub area_id: i32,
+    pub coverage_distance: i32,
+    pub assignments: Vec<DispatchAssignmentDto>,
+    pub total_distance: i64,
+    // 配車後、coverage_distance 以内に対応可能なレッカー車がいなくなる対応待ちの依頼
+    pub uncovered_order_ids: Vec<i32>,
+    // uncovered_order_ids のうち、配車前は coverage_distance 以内にレッカー車がいた依頼
+    pub newly_uncovered_order_ids: Vec<i32>,
+    pub available_tow_truck_count_after: usize,
+    // エリアのノードのうち、coverage_distance 以内に対応可能なレッカー車がいる割合
+    pub coverage_before: f64,
+    pub coverage_after: f64,
+}
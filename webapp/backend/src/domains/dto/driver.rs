@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+use super::map::RouteDto;
+
+// Input Data Structure
+
+// ドライバーが WebSocket で送ってくるメッセージ
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DriverRequestDto {
+    Location { node_id: i32 },
+    Status { order_id: i32, status: String },
+}
+
+// Output Data Structure
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DriverResponseDto {
+    Assignment {
+        order_id: i32,
+        node_id: i32,
+        route: Option<RouteDto>,
+    },
+    Ack,
+    Error {
+        message: String,
+    },
+}
@@ -1,6 +1,8 @@
-// Input Data Structure
+use serde::{Deserialize, Serialize};
+
+use crate::models::graph::{Route, RouteStep};
 
-use serde::Deserialize;
+// Input Data Structure
 
 #[derive(Deserialize, Debug)]
 pub struct UpdateEdgeRequestDto {
@@ -8,3 +10,37 @@ pub struct UpdateEdgeRequestDto {
     pub node_b_id: i32,
     pub weight: i32,
 }
+
+// Output Data Structure
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RouteStepDto {
+    pub node_id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub weight: i32,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RouteDto {
+    pub distance: i32,
+    pub steps: Vec<RouteStepDto>,
+}
+
+impl RouteDto {
+    pub fn from_entity(entity: Route) -> Self {
+        RouteDto {
+            distance: entity.distance,
+            steps: entity
+                .steps
+                .into_iter()
+                .map(|step: RouteStep| RouteStepDto {
+                    node_id: step.node_id,
+                    x: step.x,
+                    y: step.y,
+                    weight: step.weight,
+                })
+                .collect(),
+        }
+    }
+}
@@ -8,7 +8,7 @@ use crate::models::order::CompletedOrder;
 
 #[derive(Deserialize, Debug)]
 pub struct ClientOrderRequestDto {
-    pub client_id: i32,
+    pub client_id: Option<i32>,
     pub node_id: i32,
     pub car_value: f64,
 }
@@ -16,7 +16,7 @@ pub struct ClientOrderRequestDto {
 #[derive(Deserialize, Debug)]
 pub struct DispatcherOrderRequestDto {
     pub order_id: i32,
-    pub dispatcher_id: i32,
+    pub dispatcher_id: Option<i32>,
     pub tow_truck_id: i32,
     pub order_time: DateTime<Utc>,
 }
@@ -27,9 +27,14 @@ pub struct UpdateOrderStatusRequestDto {
     pub status: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct CancelOrderRequestDto {
+    pub reason: String,
+}
+
 // Output Data Structure
 
-#[derive(Serialize, FromRow, Debug)]
+#[derive(Serialize, FromRow, Clone, Debug)]
 pub struct OrderDto {
     pub id: i32,
     pub client_id: i32,
@@ -46,6 +51,9 @@ pub struct OrderDto {
     pub car_value: f64,
     pub order_time: DateTime<Utc>,
     pub completed_time: Option<DateTime<Utc>>,
+    pub cancelled_by: Option<i32>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+    pub cancel_reason: Option<String>,
 }
 
 #[derive(FromRow, Serialize, Debug)]
@@ -65,6 +73,9 @@ pub struct OrderWithDetails {
     pub car_value: f64,
     pub order_time: chrono::DateTime<chrono::Utc>,
     pub completed_time: Option<chrono::DateTime<chrono::Utc>>,
+    pub cancelled_by: Option<i32>,
+    pub cancelled_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub cancel_reason: Option<String>,
 }
 
 
@@ -90,3 +101,11 @@ impl CompletedOrderDto {
         }
     }
 }
+
+#[derive(Serialize, Clone, Debug)]
+pub struct OrderEventDto {
+    #[serde(flatten)]
+    pub order: OrderDto,
+    // 割り当てられたレッカー車が依頼の場所に着くまでの予想時間
+    pub eta_seconds: Option<i64>,
+}
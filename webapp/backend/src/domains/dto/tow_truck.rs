@@ -1,16 +1,19 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use super::map::RouteDto;
+
 // Input Data Structure
 
 #[derive(Deserialize, Debug)]
 pub struct UpdateLocationRequestDto {
-    pub tow_truck_id: i32,
+    pub tow_truck_id: Option<i32>,
     pub node_id: i32,
 }
 
 // Output Data Structure
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Clone, Debug)]
 pub struct TowTruckDto {
     pub id: i32,
     pub driver_user_id: i32,
@@ -32,3 +35,32 @@ impl TowTruckDto {
         }
     }
 }
+
+#[derive(Serialize, Clone)]
+pub struct NearestTowTruckDto {
+    #[serde(flatten)]
+    pub tow_truck: TowTruckDto,
+    pub distance: i32,
+    pub eta_seconds: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<RouteDto>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct TowTruckLocationDto {
+    pub node_id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl TowTruckLocationDto {
+    pub fn from_entity(entity: crate::models::tow_truck::TowTruckLocation) -> Self {
+        TowTruckLocationDto {
+            node_id: entity.node_id,
+            x: entity.x,
+            y: entity.y,
+            timestamp: entity.timestamp,
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use tokio::sync::broadcast;
+
+// 購読者の受信が遅れた場合に保持しておく通知の件数。溢れた分は購読者側で読み飛ばされる。
+const EVENT_BUS_CAPACITY: usize = 1024;
+
+// 依頼やレッカー車の状態の変化をプロセス内の購読者に配信する
+#[derive(Debug)]
+pub struct EventBus<T: Clone> {
+    sender: broadcast::Sender<T>,
+}
+
+impl<T: Clone> Default for EventBus<T> {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        EventBus { sender }
+    }
+}
+
+impl<T: Clone> EventBus<T> {
+    pub fn new() -> Self {
+        EventBus::default()
+    }
+
+    pub fn publish(&self, event: T) {
+        // 購読者がいなければ送信に失敗するが、通知先がないだけなので無視する
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<T> {
+        self.sender.subscribe()
+    }
+}
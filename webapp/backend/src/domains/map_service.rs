@@ -1,3 +1,6 @@
+use std::sync::Arc;
+
+use super::area_graph_store::AreaGraphStore;
 use crate::{
     errors::AppError,
     models::graph::{Edge, Node},
@@ -18,11 +21,15 @@ pub trait MapRepository {
 #[derive(Debug)]
 pub struct MapService<T: MapRepository + std::fmt::Debug> {
     repository: T,
+    area_graph_store: Arc<AreaGraphStore>,
 }
 
 impl<T: MapRepository + std::fmt::Debug> MapService<T> {
-    pub fn new(repository: T) -> Self {
-        MapService { repository }
+    pub fn new(repository: T, area_graph_store: Arc<AreaGraphStore>) -> Self {
+        MapService {
+            repository,
+            area_graph_store,
+        }
     }
 
     pub async fn update_edge(
@@ -34,6 +41,8 @@ impl<T: MapRepository + std::fmt::Debug> MapService<T> {
         self.repository
             .update_edge(node_a_id, node_b_id, weight)
             .await?;
+        self.area_graph_store
+            .update_edge(node_a_id, node_b_id, weight);
 
         Ok(())
     }
@@ -1,5 +1,9 @@
+pub mod area_graph_store;
 pub mod auth_service;
+pub mod dispatch_service;
 pub mod dto;
+pub mod event_bus;
 pub mod map_service;
 pub mod order_service;
+pub mod session_cache;
 pub mod tow_truck_service;
@@ -1,46 +1,70 @@
-use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::sync::Arc;
+
 use crate::domains::dto::order::OrderWithDetails;
+use chrono::{DateTime, Utc};
+use log::warn;
+use tokio::sync::broadcast;
 
 use super::{
     auth_service::AuthRepository,
     dto::order::{CompletedOrderDto, OrderDto},
+    dto::tow_truck::TowTruckDto,
+    event_bus::EventBus,
     map_service::MapRepository,
     tow_truck_service::TowTruckRepository,
 };
 use crate::{
     errors::AppError,
-    models::order::{CompletedOrder, Order},
+    models::{
+        order::{CompletedOrder, Order, OrderStatus},
+        tow_truck::TowTruck,
+        user::{Role, User},
+    },
+    utils::restrict_area,
 };
 
 pub trait OrderRepository {
     async fn find_order_by_id(&self, id: i32) -> Result<Order, AppError>;
-    async fn update_order_status(&self, order_id: i32, status: &str) -> Result<(), AppError>;
-    async fn get_paginated_orders(
+    // 配車されてから完了・キャンセルされるまでの依頼を返す
+    async fn find_active_order_by_tow_truck_id(
         &self,
-        page: i32,
-        page_size: i32,
-        sort_by: Option<String>,
-        sort_order: Option<String>,
-        status: Option<String>,
-        area: Option<i32>,
-    ) -> Result<Vec<Order>, AppError>;
+        tow_truck_id: i32,
+    ) -> Result<Option<Order>, AppError>;
+    // 現在の状態が current_status のときだけ更新する。既に変わっていれば Conflict を返す。
+    // release_tow_truck_id を指定すると、同じトランザクションでそのレッカー車を対応可能に戻す。
+    async fn update_order_status(
+        &self,
+        order_id: i32,
+        current_status: &str,
+        status: &str,
+        completed_time: Option<DateTime<Utc>>,
+        release_tow_truck_id: Option<i32>,
+    ) -> Result<(), AppError>;
     async fn create_order(
         &self,
         customer_id: i32,
         node_id: i32,
         car_value: f64,
     ) -> Result<(), AppError>;
-    async fn update_order_dispatched(
+    // 注文とレッカー車の行をロックし、pending / available であることを確認してから
+    // (order_id, tow_truck_id) の組をすべて 1 つのトランザクションで配車する。
+    // いずれかが既に割り当て済みなら何も配車せずに Conflict を返す。
+    async fn dispatch_orders(
         &self,
-        id: i32,
         dispatcher_id: i32,
-        tow_truck_id: i32,
+        assignments: &[(i32, i32)],
+        completed_time: DateTime<Utc>,
     ) -> Result<(), AppError>;
-    async fn create_completed_order(
+    // キャンセルと、割り当て済みのレッカー車の解放を 1 つのトランザクションで行う
+    async fn cancel_order(
         &self,
         order_id: i32,
-        tow_truck_id: i32,
-        completed_time: DateTime<Utc>,
+        current_status: &str,
+        cancelled_by: i32,
+        cancelled_at: DateTime<Utc>,
+        reason: &str,
+        release_tow_truck_id: Option<i32>,
     ) -> Result<(), AppError>;
     async fn get_all_completed_orders(&self) -> Result<Vec<CompletedOrder>, AppError>;
     async fn get_paginated_orders_with_details(
@@ -66,6 +90,8 @@ pub struct OrderService<
     tow_truck_repository: U,
     auth_repository: V,
     map_repository: W,
+    tow_truck_event_bus: Arc<EventBus<TowTruckDto>>,
+    order_event_bus: Arc<EventBus<OrderDto>>,
 }
 
 impl<
@@ -80,19 +106,124 @@ impl<
         tow_truck_repository: U,
         auth_repository: V,
         map_repository: W,
+        tow_truck_event_bus: Arc<EventBus<TowTruckDto>>,
+        order_event_bus: Arc<EventBus<OrderDto>>,
     ) -> Self {
         OrderService {
             order_repository,
             tow_truck_repository,
             auth_repository,
             map_repository,
+            tow_truck_event_bus,
+            order_event_bus,
+        }
+    }
+
+    pub fn subscribe_order_events(&self) -> broadcast::Receiver<OrderDto> {
+        self.order_event_bus.subscribe()
+    }
+
+    // 依頼者本人、担当エリアのディスパッチャー、割り当てられたドライバーだけが依頼を参照できる
+    pub async fn get_order_for_user(
+        &self,
+        order_id: i32,
+        user: &User,
+        dispatcher_area_id: Option<i32>,
+        tow_truck_id: Option<i32>,
+    ) -> Result<OrderDto, AppError> {
+        let order = self.get_order_by_id(order_id).await?;
+        let allowed = match user.role.parse::<Role>() {
+            Ok(Role::Admin) => true,
+            Ok(Role::Client) => order.client_id == user.id,
+            Ok(Role::Dispatcher) => dispatcher_area_id == Some(order.area_id),
+            Ok(Role::Driver) => tow_truck_id.is_some() && order.tow_truck_id == tow_truck_id,
+            Err(_) => false,
+        };
+        if !allowed {
+            return Err(AppError::Forbidden);
+        }
+
+        Ok(order)
+    }
+
+    pub async fn get_active_order_by_tow_truck_id(
+        &self,
+        tow_truck_id: i32,
+    ) -> Result<Option<OrderDto>, AppError> {
+        match self
+            .order_repository
+            .find_active_order_by_tow_truck_id(tow_truck_id)
+            .await?
+        {
+            Some(order) => Ok(Some(self.get_order_by_id(order.id).await?)),
+            None => Ok(None),
         }
     }
 
-    pub async fn update_order_status(&self, order_id: i32, status: &str) -> Result<(), AppError> {
+    pub async fn update_order_status(
+        &self,
+        order_id: i32,
+        status: &str,
+        assigned_tow_truck_id: Option<i32>,
+        dispatcher_area_id: Option<i32>,
+    ) -> Result<(), AppError> {
+        let next_status: OrderStatus = status.parse()?;
+        // 配車はレッカー車の割り当てを伴うため /api/order/dispatcher から、
+        // キャンセルは理由と実行者を記録するため /api/order/{id}/cancel からのみ行う
+        if matches!(
+            next_status,
+            OrderStatus::Dispatched | OrderStatus::Cancelled
+        ) {
+            return Err(AppError::BadRequest);
+        }
+
+        let order = self.order_repository.find_order_by_id(order_id).await?;
+        if assigned_tow_truck_id.is_some() && order.tow_truck_id != assigned_tow_truck_id {
+            return Err(AppError::Forbidden);
+        }
+        // ディスパッチャーは担当エリアの依頼しか更新できない
+        if let Some(dispatcher_area_id) = dispatcher_area_id {
+            let area_id = self
+                .map_repository
+                .get_area_id_by_node_id(order.node_id)
+                .await?;
+            if dispatcher_area_id != area_id {
+                return Err(AppError::Forbidden);
+            }
+        }
+
+        let current_status: OrderStatus = order
+            .status
+            .parse()
+            .map_err(|_| AppError::InternalServerError)?;
+        if !current_status.can_transition_to(next_status) {
+            return Err(AppError::Conflict);
+        }
+
+        let completed_time = match next_status {
+            OrderStatus::Completed => Some(Utc::now()),
+            _ => None,
+        };
+        let release_tow_truck_id = match next_status.releases_tow_truck() {
+            true => order.tow_truck_id,
+            false => None,
+        };
         self.order_repository
-            .update_order_status(order_id, status)
-            .await
+            .update_order_status(
+                order_id,
+                current_status.as_str(),
+                next_status.as_str(),
+                completed_time,
+                release_tow_truck_id,
+            )
+            .await?;
+
+        if let Some(tow_truck_id) = release_tow_truck_id {
+            self.publish_tow_truck(tow_truck_id).await;
+        }
+        self.publish_order(order_id).await;
+
+        Ok(())
     }
 
 pub async fn get_order_by_id(&self, id: i32) -> Result<OrderDto, AppError> {
@@ -114,10 +245,14 @@ pub async fn get_order_by_id(&self, id: i32) -> Result<OrderDto, AppError> {
         car_value: order.car_value,
         order_time: order.order_time,
         completed_time: order.completed_time,
+        cancelled_by: order.cancelled_by,
+        cancelled_at: order.cancelled_at,
+        cancel_reason: order.cancel_reason,
     })
 }
 
 // service
+#[allow(clippy::too_many_arguments)]
 pub async fn get_paginated_orders(
         &self,
         page: i32,
@@ -126,7 +261,9 @@ pub async fn get_paginated_orders(
         sort_order: Option<String>,
         status: Option<String>,
         area: Option<i32>,
+        dispatcher_area_id: Option<i32>,
     ) -> Result<Vec<OrderDto>, AppError> {
+        let area = restrict_area(area, dispatcher_area_id)?;
         let orders_with_details = self
             .order_repository
             .get_paginated_orders_with_details(page, page_size, sort_by, sort_order, status, area)
@@ -149,6 +286,9 @@ pub async fn get_paginated_orders(
                 car_value: order.car_value,
                 order_time: order.order_time,
                 completed_time: order.completed_time,
+                cancelled_by: order.cancelled_by,
+                cancelled_at: order.cancelled_at,
+                cancel_reason: order.cancel_reason,
             }
         }).collect();
 
@@ -178,26 +318,165 @@ pub async fn get_paginated_orders(
         tow_truck_id: i32,
         order_time: DateTime<Utc>,
     ) -> Result<(), AppError> {
-        if (self
-            .order_repository
-            .create_completed_order(order_id, tow_truck_id, order_time)
-            .await)
-            .is_err()
+        self.create_dispatcher_orders(dispatcher_id, &[(order_id, tow_truck_id)], order_time)
+            .await
+    }
+
+    // 複数の依頼にまとめて配車する。すべて配車できるか、何も配車しないかのどちらかになる。
+    pub async fn create_dispatcher_orders(
+        &self,
+        dispatcher_id: i32,
+        assignments: &[(i32, i32)],
+        order_time: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        let order_ids: HashSet<i32> = assignments.iter().map(|(order_id, _)| *order_id).collect();
+        let tow_truck_ids: HashSet<i32> = assignments
+            .iter()
+            .map(|(_, tow_truck_id)| *tow_truck_id)
+            .collect();
+        if assignments.is_empty()
+            || order_ids.len() != assignments.len()
+            || tow_truck_ids.len() != assignments.len()
         {
             return Err(AppError::BadRequest);
         }
 
+        let dispatcher = self
+            .auth_repository
+            .find_dispatcher_by_id(dispatcher_id)
+            .await?
+            .ok_or(AppError::BadRequest)?;
+
+        let mut tow_trucks = Vec::with_capacity(assignments.len());
+        for &(order_id, tow_truck_id) in assignments {
+            let order = self.order_repository.find_order_by_id(order_id).await?;
+            let area_id = self
+                .map_repository
+                .get_area_id_by_node_id(order.node_id)
+                .await?;
+
+            // ディスパッチャーは担当エリアの依頼にしか配車できない
+            if dispatcher.area_id != area_id {
+                return Err(AppError::Forbidden);
+            }
+
+            // エリアをまたいでレッカー車を割り当てることはできない
+            let tow_truck = self
+                .tow_truck_repository
+                .find_tow_truck_by_id(tow_truck_id)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            if tow_truck.area_id != area_id {
+                return Err(AppError::BadRequest);
+            }
+            tow_trucks.push(tow_truck);
+        }
+
         self.order_repository
-            .update_order_dispatched(order_id, dispatcher_id, tow_truck_id)
+            .dispatch_orders(dispatcher_id, assignments, order_time)
             .await?;
 
-        self.tow_truck_repository
-            .update_status(tow_truck_id, "busy")
+        for tow_truck in tow_trucks {
+            self.tow_truck_event_bus
+                .publish(TowTruckDto::from_entity(TowTruck {
+                    status: "busy".to_string(),
+                    ..tow_truck
+                }));
+        }
+        for &(order_id, _) in assignments {
+            self.publish_order(order_id).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn cancel_order(
+        &self,
+        order_id: i32,
+        user: &User,
+        reason: &str,
+    ) -> Result<(), AppError> {
+        if reason.trim().is_empty() {
+            return Err(AppError::BadRequest);
+        }
+
+        let order = self.order_repository.find_order_by_id(order_id).await?;
+        let current_status: OrderStatus = order
+            .status
+            .parse()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        // 顧客は自分の依頼を配車前に、ディスパッチャーは担当エリアの依頼を配車後にキャンセルできる
+        let cancellable_status = match user.role.parse::<Role>() {
+            Ok(Role::Client) if order.client_id == user.id => OrderStatus::Pending,
+            Ok(Role::Dispatcher) => {
+                let dispatcher = self
+                    .auth_repository
+                    .find_dispatcher_by_user_id(user.id)
+                    .await?
+                    .ok_or(AppError::Forbidden)?;
+                let area_id = self
+                    .map_repository
+                    .get_area_id_by_node_id(order.node_id)
+                    .await?;
+                if dispatcher.area_id != area_id {
+                    return Err(AppError::Forbidden);
+                }
+                OrderStatus::Dispatched
+            }
+            _ => return Err(AppError::Forbidden),
+        };
+        if current_status != cancellable_status
+            || !current_status.can_transition_to(OrderStatus::Cancelled)
+        {
+            return Err(AppError::Conflict);
+        }
+
+        self.order_repository
+            .cancel_order(
+                order_id,
+                current_status.as_str(),
+                user.id,
+                Utc::now(),
+                reason,
+                order.tow_truck_id,
+            )
             .await?;
 
+        if let Some(tow_truck_id) = order.tow_truck_id {
+            self.publish_tow_truck(tow_truck_id).await;
+        }
+        self.publish_order(order_id).await;
+
         Ok(())
     }
 
+    // 依頼の最新の状態を購読者 (依頼者やドライバー) に知らせる
+    async fn publish_order(&self, order_id: i32) {
+        match self.get_order_by_id(order_id).await {
+            Ok(order) => self.order_event_bus.publish(order),
+            Err(e) => warn!("依頼 {} の状態変更の通知に失敗しました: {:?}", order_id, e),
+        }
+    }
+
+    // レッカー車の最新の状態を購読者に知らせる
+    async fn publish_tow_truck(&self, tow_truck_id: i32) {
+        match self
+            .tow_truck_repository
+            .find_tow_truck_by_id(tow_truck_id)
+            .await
+        {
+            Ok(Some(tow_truck)) => self
+                .tow_truck_event_bus
+                .publish(TowTruckDto::from_entity(tow_truck)),
+            Ok(None) => {}
+            Err(e) => warn!(
+                "レッカー車 {} の状態変更の通知に失敗しました: {:?}",
+                tow_truck_id, e
+            ),
+        }
+    }
+
     pub async fn get_completed_orders(&self) -> Result<Vec<CompletedOrderDto>, AppError> {
         let orders = self.order_repository.get_all_completed_orders().await?;
         let order_dtos = orders
@@ -0,0 +1,241 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+
+use crate::models::user::{Session, User};
+
+#[derive(Debug)]
+struct CachedSession {
+    session: Session,
+    user: User,
+    cached_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct CachedSessions {
+    entries: HashMap<String, CachedSession>,
+    // キャッシュした順のトークン。無効化や入れ直しで古くなった記録も残るため、
+    // 取り出すときに entries の cached_at と一致するか確かめる。
+    order: VecDeque<(String, Instant)>,
+}
+
+impl CachedSessions {
+    fn remove_if_current(&mut self, session_token: &str, cached_at: Instant) {
+        if self
+            .entries
+            .get(session_token)
+            .is_some_and(|entry| entry.cached_at == cached_at)
+        {
+            self.entries.remove(session_token);
+        }
+    }
+}
+
+// セッショントークンから解決したユーザーを一定時間だけ保持する。
+// 件数に上限を設け、あふれた場合は最も古いエントリから捨てる。
+#[derive(Debug)]
+pub struct SessionCache {
+    sessions: Mutex<CachedSessions>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl SessionCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        SessionCache {
+            sessions: Mutex::new(CachedSessions::default()),
+            capacity,
+            ttl,
+        }
+    }
+
+    pub fn get(&self, session_token: &str) -> Option<(Session, User)> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.entries.get(session_token) {
+            Some(entry)
+                if entry.cached_at.elapsed() < self.ttl
+                    && entry.session.expires_at > Utc::now() =>
+            {
+                Some((entry.session.clone(), entry.user.clone()))
+            }
+            Some(_) => {
+                sessions.entries.remove(session_token);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, session: Session, user: User) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+
+        // 古い順に並んでいるため、期限切れのエントリは先頭から順に捨てられる
+        while let Some((session_token, cached_at)) = sessions
+            .order
+            .front()
+            .filter(|(_, cached_at)| now.duration_since(*cached_at) >= self.ttl)
+            .cloned()
+        {
+            sessions.order.pop_front();
+            sessions.remove_if_current(&session_token, cached_at);
+        }
+        if !sessions.entries.contains_key(&session.session_token) {
+            while sessions.entries.len() >= self.capacity {
+                match sessions.order.pop_front() {
+                    Some((session_token, cached_at)) => {
+                        sessions.remove_if_current(&session_token, cached_at)
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        let session_token = session.session_token.clone();
+        sessions.entries.insert(
+            session_token.clone(),
+            CachedSession {
+                session,
+                user,
+                cached_at: now,
+            },
+        );
+        sessions.order.push_back((session_token, now));
+
+        // 古くなった記録が溜まりすぎたら、まとめて取り除く
+        if sessions.order.len() > self.capacity * 2 {
+            let CachedSessions { entries, order } = &mut *sessions;
+            order.retain(|(session_token, cached_at)| {
+                entries
+                    .get(session_token)
+                    .is_some_and(|entry| entry.cached_at == *cached_at)
+            });
+        }
+    }
+
+    pub fn invalidate(&self, session_token: &str) {
+        self.sessions.lock().unwrap().entries.remove(session_token);
+    }
+
+    pub fn invalidate_user(&self, user_id: i32) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .entries
+            .retain(|_, entry| entry.user.id != user_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(session_token: &str, user_id: i32) -> (Session, User) {
+        let now = Utc::now();
+        let session = Session {
+            id: user_id,
+            user_id,
+            session_token: session_token.to_string(),
+            is_valid: true,
+            last_seen_at: now,
+            expires_at: now + chrono::Duration::hours(1),
+        };
+        let user = User {
+            id: user_id,
+            username: format!("user{}", user_id),
+            password: String::new(),
+            role: "client".to_string(),
+        };
+        (session, user)
+    }
+
+    fn insert(cache: &SessionCache, session_token: &str, user_id: i32) {
+        let (session, user) = session(session_token, user_id);
+        cache.insert(session, user);
+    }
+
+    #[test]
+    fn returns_cached_user() {
+        let cache = SessionCache::new(10, Duration::from_secs(60));
+        insert(&cache, "a", 1);
+
+        let (session, user) = cache.get("a").unwrap();
+        assert_eq!(session.session_token, "a");
+        assert_eq!(user.id, 1);
+        assert!(cache.get("b").is_none());
+    }
+
+    #[test]
+    fn evicts_oldest_entry_when_full() {
+        let cache = SessionCache::new(2, Duration::from_secs(60));
+        insert(&cache, "a", 1);
+        insert(&cache, "b", 2);
+        insert(&cache, "c", 3);
+
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn reinserting_does_not_evict_other_entries() {
+        let cache = SessionCache::new(2, Duration::from_secs(60));
+        insert(&cache, "a", 1);
+        insert(&cache, "b", 2);
+        insert(&cache, "a", 1);
+        insert(&cache, "a", 1);
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_some());
+    }
+
+    #[test]
+    fn skips_invalidated_entries_when_evicting() {
+        let cache = SessionCache::new(2, Duration::from_secs(60));
+        insert(&cache, "a", 1);
+        insert(&cache, "b", 2);
+        cache.invalidate("a");
+        insert(&cache, "c", 3);
+
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn drops_entries_after_ttl() {
+        let cache = SessionCache::new(10, Duration::ZERO);
+        insert(&cache, "a", 1);
+
+        assert!(cache.get("a").is_none());
+    }
+
+    #[test]
+    fn invalidates_all_sessions_of_user() {
+        let cache = SessionCache::new(10, Duration::from_secs(60));
+        insert(&cache, "a", 1);
+        insert(&cache, "b", 1);
+        insert(&cache, "c", 2);
+        cache.invalidate_user(1);
+
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn keeps_order_bounded() {
+        let cache = SessionCache::new(2, Duration::from_secs(60));
+        for _ in 0..100 {
+            insert(&cache, "a", 1);
+            cache.invalidate("a");
+        }
+
+        assert!(cache.sessions.lock().unwrap().order.len() <= 4);
+    }
+}
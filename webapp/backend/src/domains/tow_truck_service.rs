@@ -1,9 +1,25 @@
-use super::dto::tow_truck::TowTruckDto;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use tokio::sync::broadcast;
+
+use super::area_graph_store::AreaGraphStore;
+use super::dto::map::RouteDto;
+use super::dto::tow_truck::{NearestTowTruckDto, TowTruckDto, TowTruckLocationDto};
+use super::event_bus::EventBus;
 use super::map_service::MapRepository;
 use super::order_service::OrderRepository;
 use crate::errors::AppError;
-use crate::models::graph::Graph;
-use crate::models::tow_truck::TowTruck;
+use crate::models::graph::{travel_distance, travel_seconds, Graph, MAX_DISPATCH_DISTANCE};
+use crate::models::tow_truck::{TowTruck, TowTruckLocation, TowTruckPosition};
+use crate::utils::restrict_area;
+
+// 前回の位置情報からの経過時間がこれより短くても、この秒数分の移動は受け入れる (送信の遅れを見込む)
+const LOCATION_UPDATE_MIN_SECONDS: i64 = 30;
+// 渋滞のない道では到着予想時間の想定より速く走れるため、その倍率までの移動は受け入れる
+const LOCATION_UPDATE_SPEED_TOLERANCE: i64 = 2;
 
 pub trait TowTruckRepository {
     async fn get_paginated_tow_trucks(
@@ -14,8 +30,19 @@ pub trait TowTruckRepository {
         area_id: Option<i32>,
     ) -> Result<Vec<TowTruck>, AppError>;
     async fn update_location(&self, truck_id: i32, node_id: i32) -> Result<(), AppError>;
-    async fn update_status(&self, truck_id: i32, status: &str) -> Result<(), AppError>;
     async fn find_tow_truck_by_id(&self, id: i32) -> Result<Option<TowTruck>, AppError>;
+    // 位置情報が一度も記録されていないレッカー車も返す
+    async fn find_tow_truck_position(&self, id: i32) -> Result<Option<TowTruckPosition>, AppError>;
+    async fn find_tow_truck_id_by_driver_id(&self, driver_id: i32)
+        -> Result<Option<i32>, AppError>;
+    async fn get_paginated_locations(
+        &self,
+        tow_truck_id: i32,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        page: i32,
+        page_size: i32,
+    ) -> Result<Vec<TowTruckLocation>, AppError>;
 }
 
 #[derive(Debug)]
@@ -27,6 +54,8 @@ pub struct TowTruckService<
     tow_truck_repository: T,
     order_repository: U,
     map_repository: V,
+    area_graph_store: Arc<AreaGraphStore>,
+    tow_truck_event_bus: Arc<EventBus<TowTruckDto>>,
 }
 
 impl<
@@ -35,11 +64,19 @@ impl<
         V: MapRepository + std::fmt::Debug,
     > TowTruckService<T, U, V>
 {
-    pub fn new(tow_truck_repository: T, order_repository: U, map_repository: V) -> Self {
+    pub fn new(
+        tow_truck_repository: T,
+        order_repository: U,
+        map_repository: V,
+        area_graph_store: Arc<AreaGraphStore>,
+        tow_truck_event_bus: Arc<EventBus<TowTruckDto>>,
+    ) -> Self {
         TowTruckService {
             tow_truck_repository,
             order_repository,
             map_repository,
+            area_graph_store,
+            tow_truck_event_bus,
         }
     }
 
@@ -48,13 +85,24 @@ impl<
         Ok(tow_truck.map(TowTruckDto::from_entity))
     }
 
+    pub async fn get_tow_truck_id_by_driver_id(
+        &self,
+        driver_id: i32,
+    ) -> Result<Option<i32>, AppError> {
+        self.tow_truck_repository
+            .find_tow_truck_id_by_driver_id(driver_id)
+            .await
+    }
+
     pub async fn get_all_tow_trucks(
         &self,
         page: i32,
         page_size: i32,
         status: Option<String>,
         area: Option<i32>,
+        dispatcher_area_id: Option<i32>,
     ) -> Result<Vec<TowTruckDto>, AppError> {
+        let area = restrict_area(area, dispatcher_area_id)?;
         let tow_trucks = self
             .tow_truck_repository
             .get_paginated_tow_trucks(page, page_size, status, area)
@@ -67,66 +115,321 @@ impl<
         Ok(tow_truck_dtos)
     }
 
+    // 走行履歴を古い順に返す。担当エリア外のレッカー車の履歴は参照できない。
+    pub async fn get_location_history(
+        &self,
+        truck_id: i32,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        page: i32,
+        page_size: i32,
+        dispatcher_area_id: Option<i32>,
+    ) -> Result<Vec<TowTruckLocationDto>, AppError> {
+        if let (Some(from), Some(to)) = (from, to) {
+            if from > to {
+                return Err(AppError::BadRequest);
+            }
+        }
+
+        let tow_truck = self
+            .tow_truck_repository
+            .find_tow_truck_by_id(truck_id)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        restrict_area(Some(tow_truck.area_id), dispatcher_area_id)?;
+
+        let locations = self
+            .tow_truck_repository
+            .get_paginated_locations(truck_id, from, to, page, page_size)
+            .await?;
+
+        Ok(locations
+            .into_iter()
+            .map(TowTruckLocationDto::from_entity)
+            .collect())
+    }
+
     pub async fn update_location(&self, truck_id: i32, node_id: i32) -> Result<(), AppError> {
+        let position = self
+            .tow_truck_repository
+            .find_tow_truck_position(truck_id)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        let graph = self
+            .area_graph_store
+            .get_or_load(&self.map_repository, position.area_id)
+            .await?;
+        validate_location_update(&mut graph.lock().unwrap(), &position, node_id, Utc::now())?;
+
         self.tow_truck_repository
             .update_location(truck_id, node_id)
             .await?;
 
+        if let Some(tow_truck) = self
+            .tow_truck_repository
+            .find_tow_truck_by_id(truck_id)
+            .await?
+        {
+            self.tow_truck_event_bus
+                .publish(TowTruckDto::from_entity(tow_truck));
+        }
+
         Ok(())
     }
 
-pub async fn get_nearest_available_tow_trucks(
-    &self,
-    order_id: i32,
-) -> Result<Option<TowTruckDto>, AppError> {
-    let order = self.order_repository.find_order_by_id(order_id).await?;
-    let area_id = self
-        .map_repository
-        .get_area_id_by_node_id(order.node_id)
-        .await?;
-    let tow_trucks = self
-        .tow_truck_repository
-        .get_paginated_tow_trucks(0, -1, Some("available".to_string()), Some(area_id))
-        .await?;
+    // レッカー車の現在地から指定したノードまでの経路を返す。辿り着けなければ None を返す。
+    pub async fn get_route_to_node(
+        &self,
+        truck_id: i32,
+        node_id: i32,
+    ) -> Result<Option<RouteDto>, AppError> {
+        let tow_truck = self
+            .tow_truck_repository
+            .find_tow_truck_by_id(truck_id)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        let graph = self
+            .area_graph_store
+            .get_or_load(&self.map_repository, tow_truck.area_id)
+            .await?;
+        let graph = graph.lock().unwrap();
+
+        Ok(graph
+            .shortest_route(tow_truck.node_id, node_id)
+            .map(RouteDto::from_entity))
+    }
 
-    let nodes = self.map_repository.get_all_nodes(Some(area_id)).await?;
-    let edges = self.map_repository.get_all_edges(Some(area_id)).await?;
+    // from_node_id から to_node_id までの到着予想時間 (秒)。辿り着けなければ None を返す。
+    pub async fn estimate_arrival_seconds(
+        &self,
+        area_id: i32,
+        from_node_id: i32,
+        to_node_id: i32,
+    ) -> Result<Option<i64>, AppError> {
+        let graph = self
+            .area_graph_store
+            .get_or_load(&self.map_repository, area_id)
+            .await?;
+        let distance = graph
+            .lock()
+            .unwrap()
+            .shortest_path(from_node_id, to_node_id);
 
-    let mut graph = Graph::new();
-    for node in nodes {
-        graph.add_node(node);
+        match distance {
+            i32::MAX => Ok(None),
+            distance => Ok(Some(travel_seconds(distance))),
+        }
     }
-    for edge in edges {
-        graph.add_edge(edge);
+
+    pub fn subscribe_tow_truck_events(&self) -> broadcast::Receiver<TowTruckDto> {
+        self.tow_truck_event_bus.subscribe()
     }
 
-    let sorted_tow_trucks_by_distance = {
-        let mut tow_trucks_with_distance: Vec<_> = tow_trucks
+    pub async fn get_nearest_available_tow_trucks(
+        &self,
+        order_id: i32,
+        limit: usize,
+        include_route: bool,
+        dispatcher_area_id: Option<i32>,
+    ) -> Result<Vec<NearestTowTruckDto>, AppError> {
+        let order = self.order_repository.find_order_by_id(order_id).await?;
+        let area_id = self
+            .map_repository
+            .get_area_id_by_node_id(order.node_id)
+            .await?;
+        restrict_area(Some(area_id), dispatcher_area_id)?;
+        let tow_trucks = self
+            .tow_truck_repository
+            .get_paginated_tow_trucks(0, -1, Some("available".to_string()), Some(area_id))
+            .await?;
+
+        let graph = self
+            .area_graph_store
+            .get_or_load(&self.map_repository, area_id)
+            .await?;
+        let mut graph = graph.lock().unwrap();
+
+        let mut tow_trucks_by_node_id: HashMap<i32, Vec<TowTruck>> = HashMap::new();
+        for truck in tow_trucks {
+            tow_trucks_by_node_id
+                .entry(truck.node_id)
+                .or_default()
+                .push(truck);
+        }
+        let truck_node_ids: HashSet<i32> = tow_trucks_by_node_id.keys().copied().collect();
+
+        // 注文地点を始点に一度だけ探索し、近い順に limit 個のノードが確定した時点で打ち切る
+        let nearest_nodes = graph.nearest_targets(order.node_id, &truck_node_ids, limit);
+
+        let sorted_tow_trucks_by_distance = {
+            let mut tow_trucks_with_distance: Vec<(i32, TowTruck)> = nearest_nodes
+                .into_iter()
+                .flat_map(|(node_id, distance)| {
+                    tow_trucks_by_node_id
+                        .remove(&node_id)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(move |truck| (distance, truck))
+                })
+                .collect();
+
+            tow_trucks_with_distance.sort_by_key(|(distance, truck)| (*distance, truck.id));
+            tow_trucks_with_distance
+        };
+
+        let nearest_tow_truck_dtos = sorted_tow_trucks_by_distance
             .into_iter()
-            .map(|truck| {
-                // let distance = calculate_distance(&graph, truck.node_id, order.node_id);
-                let distance = graph.shortest_path(truck.node_id, order.node_id);
-                (distance, truck)
+            .filter(|(distance, _)| *distance <= MAX_DISPATCH_DISTANCE)
+            .take(limit)
+            .map(|(distance, truck)| {
+                let route = match include_route {
+                    true => graph
+                        .shortest_route(truck.node_id, order.node_id)
+                        .map(RouteDto::from_entity),
+                    false => None,
+                };
+
+                NearestTowTruckDto {
+                    tow_truck: TowTruckDto::from_entity(truck),
+                    distance,
+                    eta_seconds: travel_seconds(distance),
+                    route,
+                }
             })
             .collect();
 
-        tow_trucks_with_distance.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
-        tow_trucks_with_distance
-    };
-
-    if sorted_tow_trucks_by_distance.is_empty() || sorted_tow_trucks_by_distance[0].0 > 10000000 {
-        return Ok(None);
+        Ok(nearest_tow_truck_dtos)
     }
-
-    let sorted_tow_truck_dtos: Vec<TowTruckDto> = sorted_tow_trucks_by_distance
-        .into_iter()
-        .map(|(_, truck)| TowTruckDto::from_entity(truck))
-        .collect();
-
-    Ok(sorted_tow_truck_dtos.first().cloned())
-}
 }
 
 // fn calculate_distance(graph: &Graph, node_id_1: i32, node_id_2: i32) -> i32 {
 //     graph.shortest_path(node_id_1, node_id_2)
 // }
+
+// 道路グラフに沿わない位置の更新 (別エリアのノードや、前回の位置から経過時間内に走って届かないノード) を拒否する
+fn validate_location_update(
+    graph: &mut Graph,
+    position: &TowTruckPosition,
+    node_id: i32,
+    now: DateTime<Utc>,
+) -> Result<(), AppError> {
+    if !graph.nodes.contains_key(&node_id) {
+        warn!(
+            "レッカー車 {} の位置情報を拒否しました: ノード {} はエリア {} に存在しません",
+            position.id, node_id, position.area_id
+        );
+        return Err(AppError::BadRequest);
+    }
+
+    // 初めての位置情報は移動元がわからないため、エリア内であれば受け入れる
+    let (last_node_id, last_updated_at) = match (position.node_id, position.location_updated_at) {
+        (Some(last_node_id), Some(last_updated_at)) => (last_node_id, last_updated_at),
+        _ => return Ok(()),
+    };
+
+    let elapsed_seconds = (now - last_updated_at)
+        .num_seconds()
+        .max(LOCATION_UPDATE_MIN_SECONDS);
+    let max_distance =
+        travel_distance(elapsed_seconds).saturating_mul(LOCATION_UPDATE_SPEED_TOLERANCE);
+    let distance = graph.shortest_path(last_node_id, node_id);
+    if distance as i64 > max_distance {
+        warn!(
+            "レッカー車 {} の位置情報を拒否しました: ノード {} から {} までの距離 {} が上限 {} を超えています",
+            position.id, last_node_id, node_id, distance, max_distance
+        );
+        return Err(AppError::BadRequest);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::models::graph::{Edge, Node};
+
+    // 1 - 2 - 3 - 4 - 5 - 6 と重み 1000 の辺でつながり、7 はどこにもつながっていない
+    fn line_graph() -> Graph {
+        let mut graph = Graph::new();
+        for id in 1..=7 {
+            graph.add_node(Node { id, x: id, y: 0 });
+        }
+        for id in 1..=5 {
+            graph.add_edge(Edge {
+                node_a_id: id,
+                node_b_id: id + 1,
+                weight: 1000,
+            });
+        }
+        graph
+    }
+
+    fn position_at(node_id: Option<i32>, updated_at: Option<DateTime<Utc>>) -> TowTruckPosition {
+        TowTruckPosition {
+            id: 1,
+            area_id: 1,
+            node_id,
+            location_updated_at: updated_at,
+        }
+    }
+
+    #[test]
+    fn accepts_moves_possible_in_the_elapsed_time() {
+        let now = Utc::now();
+        // 重み 1000 の辺は 100 秒かかるため、100 秒あれば許容の倍率込みで辺 2 本分まで進める
+        let position = position_at(Some(1), Some(now - Duration::seconds(100)));
+        let mut graph = line_graph();
+
+        assert!(validate_location_update(&mut graph, &position, 1, now).is_ok());
+        assert!(validate_location_update(&mut graph, &position, 2, now).is_ok());
+        assert!(validate_location_update(&mut graph, &position, 3, now).is_ok());
+    }
+
+    #[test]
+    fn rejects_teleports() {
+        let now = Utc::now();
+        let position = position_at(Some(1), Some(now - Duration::seconds(100)));
+        let mut graph = line_graph();
+
+        // 経過時間では届かない移動
+        assert!(validate_location_update(&mut graph, &position, 4, now).is_err());
+        // 道路でつながっていないノード
+        assert!(validate_location_update(&mut graph, &position, 7, now).is_err());
+        // エリアに存在しないノード
+        assert!(validate_location_update(&mut graph, &position, 100, now).is_err());
+    }
+
+    #[test]
+    fn allows_a_minimum_interval_between_reports() {
+        let now = Utc::now();
+        let position = position_at(Some(1), Some(now - Duration::seconds(1)));
+        let mut graph = line_graph();
+
+        // 1 秒しか経っていなくても 30 秒分 (許容の倍率込みで重み 600) までは受け入れる
+        assert!(validate_location_update(&mut graph, &position, 1, now).is_ok());
+        assert!(validate_location_update(&mut graph, &position, 2, now).is_err());
+    }
+
+    #[test]
+    fn accepts_first_report_anywhere_in_the_area() {
+        let now = Utc::now();
+        let position = position_at(None, None);
+        let mut graph = line_graph();
+
+        assert!(validate_location_update(&mut graph, &position, 6, now).is_ok());
+        assert!(validate_location_update(&mut graph, &position, 100, now).is_err());
+    }
+
+    #[test]
+    fn keeps_checking_after_a_long_silence() {
+        let now = Utc::now();
+        let position = position_at(Some(1), Some(now - Duration::hours(1)));
+        let mut graph = line_graph();
+
+        assert!(validate_location_update(&mut graph, &position, 6, now).is_ok());
+        assert!(validate_location_update(&mut graph, &position, 7, now).is_err());
+    }
+}
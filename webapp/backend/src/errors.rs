@@ -8,6 +8,8 @@ pub enum AppError {
     BadRequest,
     #[error("Unauthorized")]
     Unauthorized,
+    #[error("Forbidden")]
+    Forbidden,
     #[error("Not Found")]
     NotFound,
     #[error("Conflict")]
@@ -33,6 +35,7 @@ impl ResponseError for AppError {
         match *self {
             AppError::BadRequest => HttpResponse::BadRequest().json(error_response),
             AppError::Unauthorized => HttpResponse::Unauthorized().json(error_response),
+            AppError::Forbidden => HttpResponse::Forbidden().json(error_response),
             AppError::NotFound => HttpResponse::NotFound().json(error_response),
             AppError::Conflict => HttpResponse::Conflict().json(error_response),
             AppError::InternalServerError => {
@@ -0,0 +1,146 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use log::error;
+
+use crate::errors::AppError;
+use crate::utils::generate_session_token;
+
+// 1 つのディレクトリ直下の画像ファイルだけを扱う。
+// ファイル名は DB やリクエストに由来するため、ディレクトリの外を指す名前は存在しないものとして扱う。
+#[derive(Debug, Clone)]
+pub struct ImageStore {
+    root: PathBuf,
+}
+
+impl ImageStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        ImageStore { root: root.into() }
+    }
+
+    // 保存済みの画像の実パスを返す。シンボリックリンクも解決した上でディレクトリ内に収まっているか確認する。
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, AppError> {
+        let path = self.path_for(name)?;
+        let root = self.root.canonicalize().map_err(|_| AppError::NotFound)?;
+        let path = path.canonicalize().map_err(|_| AppError::NotFound)?;
+        if !path.starts_with(&root) || !path.is_file() {
+            error!("画像ディレクトリ外のファイルが指定されました: {:?}", name);
+            return Err(AppError::NotFound);
+        }
+        Ok(path)
+    }
+
+    // 同じ画像を同時に書き込んでも壊れたファイルを配信しないよう、一時ファイルを経由して置き換える
+    pub fn write(&self, name: &str, image_byte: &[u8]) -> Result<PathBuf, AppError> {
+        let path = self.path_for(name)?;
+        let temporary_path = self
+            .root
+            .join(format!(".{}.{}.tmp", name, generate_session_token()));
+        fs::create_dir_all(&self.root)
+            .and_then(|_| fs::write(&temporary_path, image_byte))
+            .and_then(|_| fs::rename(&temporary_path, &path))
+            .map_err(|e| {
+                error!("画像の保存に失敗しました: {:?}", e);
+                let _ = fs::remove_file(&temporary_path);
+                AppError::InternalServerError
+            })?;
+        Ok(path)
+    }
+
+    fn path_for(&self, name: &str) -> Result<PathBuf, AppError> {
+        if !is_valid_image_name(name) {
+            error!("不正な画像ファイル名が指定されました: {:?}", name);
+            return Err(AppError::NotFound);
+        }
+        Ok(self.root.join(name))
+    }
+}
+
+// ディレクトリ区切りや ".." を含まない、単一のファイル名だけを受け付ける
+fn is_valid_image_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    !name.contains(['/', '\\', '\0'])
+        && !name.contains("..")
+        && matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // テストごとに一時ディレクトリの下へ専用のディレクトリを作る
+    fn temporary_store() -> (ImageStore, PathBuf) {
+        let root = std::env::temp_dir().join(format!("image_store_{}", generate_session_token()));
+        fs::create_dir_all(&root).unwrap();
+        (ImageStore::new(&root), root)
+    }
+
+    #[test]
+    fn accepts_plain_file_names() {
+        assert!(is_valid_image_name("default.png"));
+        assert!(is_valid_image_name("0123abcd_100x100.webp"));
+    }
+
+    #[test]
+    fn rejects_names_outside_the_directory() {
+        for name in [
+            "",
+            ".",
+            "..",
+            "../secret.png",
+            "a/../../secret.png",
+            "sub/image.png",
+            "/etc/passwd",
+            "..\\secret.png",
+            "image..png",
+            "image\0.png",
+        ] {
+            assert!(!is_valid_image_name(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn resolves_written_images() {
+        let (store, root) = temporary_store();
+        let written = store.write("image.png", b"png").unwrap();
+
+        let resolved = store.resolve("image.png").unwrap();
+        assert_eq!(resolved, written.canonicalize().unwrap());
+        assert_eq!(fs::read(&resolved).unwrap(), b"png");
+        assert!(matches!(
+            store.resolve("missing.png"),
+            Err(AppError::NotFound)
+        ));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rejects_traversal_on_resolve_and_write() {
+        let (store, root) = temporary_store();
+
+        assert!(matches!(
+            store.resolve("../image.png"),
+            Err(AppError::NotFound)
+        ));
+        assert!(store.write("../image.png", b"png").is_err());
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_leaving_the_directory() {
+        let (store, root) = temporary_store();
+        let outside = root.with_extension("outside.png");
+        fs::write(&outside, b"secret").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link.png")).unwrap();
+
+        assert!(matches!(store.resolve("link.png"), Err(AppError::NotFound)));
+
+        fs::remove_file(outside).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,17 +1,26 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use actix_cors::Cors;
 use actix_web::{web, App, HttpServer};
 use api::{
-    auth_handler, health_check_handler, map_handler, order_handler, result_handler,
-    tow_truck_handler,
+    auth_handler, dispatch_handler, driver_handler, health_check_handler, map_handler,
+    order_handler, result_handler, tow_truck_handler,
 };
+use domains::area_graph_store::AreaGraphStore;
+use domains::dispatch_service::{DispatchService, AUTO_DISPATCH_INTERVAL_SECONDS};
+use domains::event_bus::EventBus;
 use domains::map_service::MapService;
 use domains::{
-    auth_service::AuthService, order_service::OrderService, tow_truck_service::TowTruckService,
+    auth_service::{AuthService, SESSION_SWEEP_INTERVAL_SECONDS},
+    order_service::OrderService,
+    tow_truck_service::TowTruckService,
 };
+use log::{error, info};
 use middlewares::auth_middleware::AuthMiddleware;
+use models::user::Role;
 use repositories::auth_repository::AuthRepositoryImpl;
+use repositories::dispatch_repository::DispatchRepositoryImpl;
 use repositories::map_repository::MapRepositoryImpl;
 use repositories::order_repository::OrderRepositoryImpl;
 use repositories::tow_truck_repository::TowTruckRepositoryImpl;
@@ -34,21 +43,70 @@ async fn main() -> std::io::Result<()> {
         port = 18080;
     }
 
+    let area_graph_store = Arc::new(AreaGraphStore::new());
+    let tow_truck_event_bus = Arc::new(EventBus::new());
+    let order_event_bus = Arc::new(EventBus::new());
+
+    // ログアウト時にセッションキャッシュを破棄できるよう、ハンドラとミドルウェアで同じインスタンスを使う
     let auth_service = web::Data::new(AuthService::new(AuthRepositoryImpl::new(pool.clone())));
-    let auth_service_for_middleware =
-        Arc::new(AuthService::new(AuthRepositoryImpl::new(pool.clone())));
+    let auth_service_for_middleware = auth_service.clone().into_inner();
     let tow_truck_service = web::Data::new(TowTruckService::new(
         TowTruckRepositoryImpl::new(pool.clone()),
         OrderRepositoryImpl::new(pool.clone()),
         MapRepositoryImpl::new(pool.clone()),
+        area_graph_store.clone(),
+        tow_truck_event_bus.clone(),
     ));
     let order_service = web::Data::new(OrderService::new(
         OrderRepositoryImpl::new(pool.clone()),
         TowTruckRepositoryImpl::new(pool.clone()),
         AuthRepositoryImpl::new(pool.clone()),
         MapRepositoryImpl::new(pool.clone()),
+        tow_truck_event_bus.clone(),
+        order_event_bus.clone(),
+    ));
+    let map_service = web::Data::new(MapService::new(
+        MapRepositoryImpl::new(pool.clone()),
+        area_graph_store.clone(),
+    ));
+    let dispatch_service = web::Data::new(DispatchService::new(
+        DispatchRepositoryImpl::new(pool.clone()),
+        OrderRepositoryImpl::new(pool.clone()),
+        TowTruckRepositoryImpl::new(pool.clone()),
+        MapRepositoryImpl::new(pool.clone()),
+        area_graph_store.clone(),
     ));
-    let map_service = web::Data::new(MapService::new(MapRepositoryImpl::new(pool.clone())));
+
+    let auth_service_for_sweeper = auth_service_for_middleware.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval =
+            actix_web::rt::time::interval(Duration::from_secs(SESSION_SWEEP_INTERVAL_SECONDS));
+        loop {
+            interval.tick().await;
+            match auth_service_for_sweeper.delete_expired_sessions().await {
+                Ok(count) if count > 0 => info!("失効したセッションを {} 件削除しました", count),
+                Ok(_) => {}
+                Err(e) => error!("失効したセッションの削除に失敗しました: {:?}", e),
+            }
+        }
+    });
+
+    // 自動配車が有効なエリアの依頼を定期的に配車する
+    let dispatch_service_for_auto_dispatch = dispatch_service.clone();
+    let order_service_for_auto_dispatch = order_service.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval =
+            actix_web::rt::time::interval(Duration::from_secs(AUTO_DISPATCH_INTERVAL_SECONDS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = dispatch_service_for_auto_dispatch
+                .run_auto_dispatch(&order_service_for_auto_dispatch)
+                .await
+            {
+                error!("自動配車に失敗しました: {:?}", e);
+            }
+        }
+    });
 
     HttpServer::new(move || {
         let mut cors = Cors::default();
@@ -68,6 +126,7 @@ async fn main() -> std::io::Result<()> {
             .app_data(auth_service.clone())
             .app_data(order_service.clone())
             .app_data(map_service.clone())
+            .app_data(dispatch_service.clone())
             .wrap(cors)
             .service(
                 web::scope("/api")
@@ -90,62 +149,193 @@ async fn main() -> std::io::Result<()> {
                         web::resource("/logout")
                             .route(web::post().to(auth_handler::logout_handler)),
                     )
+                    .service(
+                        web::resource("/sessions")
+                            .wrap(AuthMiddleware::new(auth_service_for_middleware.clone()))
+                            .route(web::delete().to(auth_handler::revoke_sessions_handler)),
+                    )
+                    .service(
+                        web::resource("/user_image")
+                            .wrap(AuthMiddleware::new(auth_service_for_middleware.clone()))
+                            .route(web::put().to(auth_handler::update_user_image_handler)),
+                    )
                     .service(
                         web::resource("/user_image/{user_id}")
                             .route(web::get().to(auth_handler::user_profile_image_handler)),
                     )
+                    // EventSource はヘッダーを付けられないため、/tow_truck のスコープより先に登録する
+                    .service(
+                        web::resource("/tow_truck/stream")
+                            .wrap(
+                                AuthMiddleware::new(auth_service_for_middleware.clone())
+                                    .require_roles(&[Role::Dispatcher])
+                                    .allow_query_token(),
+                            )
+                            .route(web::get().to(tow_truck_handler::stream_tow_trucks_handler)),
+                    )
                     .service(
                         web::scope("/tow_truck")
                             .wrap(AuthMiddleware::new(auth_service_for_middleware.clone()))
-                            .service(web::resource("/list").route(
-                                web::get().to(tow_truck_handler::get_paginated_tow_trucks_handler),
-                            ))
                             .service(
-                                web::resource("/location").route(
-                                    web::post().to(tow_truck_handler::update_location_handler),
-                                ),
+                                web::resource("/list")
+                                    .wrap(
+                                        AuthMiddleware::new(auth_service_for_middleware.clone())
+                                            .require_roles(&[Role::Dispatcher, Role::Admin]),
+                                    )
+                                    .route(web::get().to(
+                                        tow_truck_handler::get_paginated_tow_trucks_handler,
+                                    )),
+                            )
+                            .service(
+                                web::resource("/location")
+                                    .wrap(
+                                        AuthMiddleware::new(auth_service_for_middleware.clone())
+                                            .require_roles(&[Role::Driver]),
+                                    )
+                                    .route(
+                                        web::post().to(tow_truck_handler::update_location_handler),
+                                    ),
+                            )
+                            .service(
+                                web::resource("/nearest")
+                                    .wrap(
+                                        AuthMiddleware::new(auth_service_for_middleware.clone())
+                                            .require_roles(&[Role::Dispatcher]),
+                                    )
+                                    .route(web::get().to(
+                                        tow_truck_handler::get_nearest_available_tow_trucks_handler,
+                                    )),
+                            )
+                            .service(
+                                web::resource("/{id}/locations")
+                                    .wrap(
+                                        AuthMiddleware::new(auth_service_for_middleware.clone())
+                                            .require_roles(&[Role::Dispatcher, Role::Admin]),
+                                    )
+                                    .route(
+                                        web::get()
+                                            .to(tow_truck_handler::get_location_history_handler),
+                                    ),
                             )
-                            .service(web::resource("/nearest").route(
-                                web::get().to(
-                                    tow_truck_handler::get_nearest_available_tow_trucks_handler,
-                                ),
-                            ))
                             .service(
                                 web::resource("/{id}")
                                     .route(web::get().to(tow_truck_handler::get_tow_truck_handler)),
                             ),
                     )
+                    // ブラウザの WebSocket もヘッダーを付けられないため、クエリのトークンを受け付ける
+                    .service(
+                        web::resource("/driver/ws")
+                            .wrap(
+                                AuthMiddleware::new(auth_service_for_middleware.clone())
+                                    .require_roles(&[Role::Driver])
+                                    .allow_query_token(),
+                            )
+                            .route(web::get().to(driver_handler::driver_websocket_handler)),
+                    )
+                    // /tow_truck/stream と同じく、/order のスコープより先に登録する
+                    .service(
+                        web::resource("/order/{id}/events")
+                            .wrap(
+                                AuthMiddleware::new(auth_service_for_middleware.clone())
+                                    .allow_query_token(),
+                            )
+                            .route(web::get().to(order_handler::stream_order_events_handler)),
+                    )
                     .service(
                         web::scope("/order")
                             .wrap(AuthMiddleware::new(auth_service_for_middleware.clone()))
                             .service(
-                                web::resource("/list").route(
-                                    web::get().to(order_handler::get_paginated_orders_handler),
-                                ),
+                                web::resource("/list")
+                                    .wrap(
+                                        AuthMiddleware::new(auth_service_for_middleware.clone())
+                                            .require_roles(&[Role::Dispatcher, Role::Admin]),
+                                    )
+                                    .route(
+                                        web::get().to(order_handler::get_paginated_orders_handler),
+                                    ),
                             )
                             .service(
-                                web::resource("/status").route(
-                                    web::post().to(order_handler::update_order_status_handler),
-                                ),
+                                web::resource("/status")
+                                    .wrap(
+                                        AuthMiddleware::new(auth_service_for_middleware.clone())
+                                            .require_roles(&[Role::Dispatcher, Role::Driver]),
+                                    )
+                                    .route(
+                                        web::post().to(order_handler::update_order_status_handler),
+                                    ),
                             )
                             .service(
-                                web::resource("/client").route(
-                                    web::post().to(order_handler::create_client_order_handler),
-                                ),
+                                web::resource("/client")
+                                    .wrap(
+                                        AuthMiddleware::new(auth_service_for_middleware.clone())
+                                            .require_roles(&[Role::Client]),
+                                    )
+                                    .route(
+                                        web::post().to(order_handler::create_client_order_handler),
+                                    ),
+                            )
+                            .service(
+                                web::resource("/dispatcher")
+                                    .wrap(
+                                        AuthMiddleware::new(auth_service_for_middleware.clone())
+                                            .require_roles(&[Role::Dispatcher]),
+                                    )
+                                    .route(web::post().to(
+                                        order_handler::create_dispatcher_order_handler,
+                                    )),
+                            )
+                            .service(
+                                web::resource("/{id}/cancel")
+                                    .wrap(
+                                        AuthMiddleware::new(auth_service_for_middleware.clone())
+                                            .require_roles(&[Role::Client, Role::Dispatcher]),
+                                    )
+                                    .route(web::post().to(order_handler::cancel_order_handler)),
                             )
-                            .service(web::resource("/dispatcher").route(
-                                web::post().to(order_handler::create_dispatcher_order_handler),
-                            ))
                             .service(
                                 web::resource("/{id}")
                                     .route(web::get().to(order_handler::get_order_handler)),
                             ),
                     )
+                    .service(
+                        web::scope("/dispatch")
+                            .wrap(
+                                AuthMiddleware::new(auth_service_for_middleware.clone())
+                                    .require_roles(&[Role::Dispatcher]),
+                            )
+                            .service(
+                                web::resource("/auto/{area_id}")
+                                    .route(
+                                        web::get().to(dispatch_handler::get_auto_dispatch_handler),
+                                    )
+                                    .route(
+                                        web::put()
+                                            .to(dispatch_handler::update_auto_dispatch_handler),
+                                    ),
+                            )
+                            .service(
+                                web::resource("/optimize").route(
+                                    web::post().to(dispatch_handler::optimize_dispatch_handler),
+                                ),
+                            )
+                            .service(
+                                web::resource("/preview").route(
+                                    web::post().to(dispatch_handler::preview_dispatch_handler),
+                                ),
+                            )
+                            .service(web::resource("/commit").route(
+                                web::post().to(dispatch_handler::commit_dispatch_plan_handler),
+                            )),
+                    )
                     .service(
                         web::scope("/map")
                             .wrap(AuthMiddleware::new(auth_service_for_middleware.clone()))
                             .service(
                                 web::resource("/update_edge")
+                                    .wrap(
+                                        AuthMiddleware::new(auth_service_for_middleware.clone())
+                                            .require_roles(&[Role::Admin]),
+                                    )
                                     .route(web::put().to(map_handler::update_edge_handler)),
                             ),
                     ),
@@ -1,22 +1,51 @@
+use std::rc::Rc;
 use std::sync::Arc;
 
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
+    web, Error, HttpMessage,
 };
 use futures_util::future::{ready, LocalBoxFuture, Ready};
+use serde::Deserialize;
 
 use crate::{
-    domains::auth_service::AuthService, repositories::auth_repository::AuthRepositoryImpl,
+    domains::auth_service::AuthService,
+    errors::AppError,
+    models::user::{Role, User},
+    repositories::auth_repository::AuthRepositoryImpl,
 };
 
+#[derive(Deserialize)]
+struct SessionTokenQuery {
+    token: String,
+}
+
 pub struct AuthMiddleware {
     auth_service: Arc<AuthService<AuthRepositoryImpl>>,
+    roles: Rc<Vec<Role>>,
+    allow_query_token: bool,
 }
 
 impl AuthMiddleware {
     pub fn new(auth_service: Arc<AuthService<AuthRepositoryImpl>>) -> Self {
-        AuthMiddleware { auth_service }
+        AuthMiddleware {
+            auth_service,
+            roles: Rc::new(Vec::new()),
+            allow_query_token: false,
+        }
+    }
+
+    // 指定したロールのユーザーだけを通す。指定しなければログイン済みのユーザーをすべて通す。
+    pub fn require_roles(mut self, roles: &[Role]) -> Self {
+        self.roles = Rc::new(roles.to_vec());
+        self
+    }
+
+    // Authorization ヘッダーがなければ ?token= のセッショントークンを使う。
+    // ブラウザの EventSource や WebSocket はヘッダーを付けられないため、ストリームにだけ使う。
+    pub fn allow_query_token(mut self) -> Self {
+        self.allow_query_token = true;
+        self
     }
 }
 
@@ -34,15 +63,19 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthMiddlewareMiddleware {
-            service,
+            service: Rc::new(service),
             auth_service: self.auth_service.clone(),
+            roles: self.roles.clone(),
+            allow_query_token: self.allow_query_token,
         }))
     }
 }
 
 pub struct AuthMiddlewareMiddleware<S> {
-    service: S,
+    service: Rc<S>,
     auth_service: Arc<AuthService<AuthRepositoryImpl>>,
+    roles: Rc<Vec<Role>>,
+    allow_query_token: bool,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddlewareMiddleware<S>
@@ -57,29 +90,46 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let auth_header = req
+        let session_token = req
             .headers()
             .get("Authorization")
             .and_then(|h| h.to_str().ok())
-            .map(|s| s.to_string());
-        let auth_header = Arc::new(auth_header);
+            .map(|s| s.to_string())
+            .or_else(|| match self.allow_query_token {
+                true => web::Query::<SessionTokenQuery>::from_query(req.query_string())
+                    .ok()
+                    .map(|query| query.into_inner().token),
+                false => None,
+            });
+        // 外側の AuthMiddleware で解決済みであれば再度問い合わせない
+        let resolved_user = req.extensions().get::<User>().cloned();
 
+        let service = self.service.clone();
         let auth_service = self.auth_service.clone();
-        let fut = self.service.call(req);
+        let roles = self.roles.clone();
 
         Box::pin(async move {
-            let is_valid_token = match &*auth_header {
-                Some(token) => auth_service.validate_session(token).await.is_ok(),
-                None => false,
+            let user = match (resolved_user, session_token) {
+                (Some(user), _) => Some(user),
+                (None, Some(token)) => auth_service.authenticate(&token).await.ok(),
+                (None, None) => None,
+            };
+
+            let user = match user {
+                Some(user) => user,
+                None => {
+                    return Err(actix_web::error::ErrorUnauthorized(
+                        "Invalid or missing token",
+                    ))
+                }
             };
 
-            if is_valid_token {
-                fut.await
-            } else {
-                Err(actix_web::error::ErrorUnauthorized(
-                    "Invalid or missing token",
-                ))
+            if !roles.is_empty() && !user.has_any_role(&roles) {
+                return Err(AppError::Forbidden.into());
             }
+
+            req.extensions_mut().insert(user);
+            service.call(req).await
         })
     }
 }
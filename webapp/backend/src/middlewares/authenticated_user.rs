@@ -0,0 +1,123 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpMessage, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+
+use crate::{
+    domains::{auth_service::AuthService, tow_truck_service::TowTruckService},
+    errors::AppError,
+    models::user::{Dispatcher, Role, User},
+    repositories::{
+        auth_repository::AuthRepositoryImpl, map_repository::MapRepositoryImpl,
+        order_repository::OrderRepositoryImpl, tow_truck_repository::TowTruckRepositoryImpl,
+    },
+};
+
+// セッションから導いた操作者。
+// ディスパッチャーなら担当のディスパッチャー情報、ドライバーなら自分のレッカー車の ID を持つ。
+pub struct AuthenticatedUser {
+    pub user: User,
+    pub dispatcher: Option<Dispatcher>,
+    pub tow_truck_id: Option<i32>,
+}
+
+impl AuthenticatedUser {
+    pub fn role(&self) -> Option<Role> {
+        self.user.role.parse().ok()
+    }
+
+    // ディスパッチャーが操作できるエリア。それ以外のロールはエリアの制限を受けない。
+    pub fn area_scope(&self) -> Result<Option<i32>, AppError> {
+        match self.role() {
+            Some(Role::Dispatcher) => self
+                .dispatcher
+                .as_ref()
+                .map(|d| Some(d.area_id))
+                .ok_or(AppError::Forbidden),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn client_id(&self, requested: Option<i32>) -> Result<i32, AppError> {
+        let own = match self.role() {
+            Some(Role::Client) => Some(self.user.id),
+            _ => None,
+        };
+        self.resolve_id(own, requested)
+    }
+
+    pub fn dispatcher_id(&self, requested: Option<i32>) -> Result<i32, AppError> {
+        self.resolve_id(self.dispatcher.as_ref().map(|d| d.id), requested)
+    }
+
+    pub fn tow_truck_id(&self, requested: Option<i32>) -> Result<i32, AppError> {
+        self.resolve_id(self.tow_truck_id, requested)
+    }
+
+    // リクエストボディで指定された ID をセッションの ID と突き合わせる。
+    // ロールにかかわらず、自分自身の ID しか指定できない。
+    fn resolve_id(&self, own: Option<i32>, requested: Option<i32>) -> Result<i32, AppError> {
+        match (own, requested) {
+            (Some(own), None) => Ok(own),
+            (Some(own), Some(requested)) if own == requested => Ok(own),
+            _ => Err(AppError::Forbidden),
+        }
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let auth_service = req
+                .app_data::<web::Data<AuthService<AuthRepositoryImpl>>>()
+                .ok_or(AppError::InternalServerError)?
+                .clone();
+
+            // AuthMiddleware で解決済みであれば再度問い合わせない
+            let resolved_user = req.extensions().get::<User>().cloned();
+            let user = match resolved_user {
+                Some(user) => user,
+                None => {
+                    let session_token = req
+                        .headers()
+                        .get("Authorization")
+                        .and_then(|h| h.to_str().ok())
+                        .ok_or(AppError::Unauthorized)?;
+                    auth_service.authenticate(session_token).await?
+                }
+            };
+
+            let dispatcher = match user.role.parse::<Role>() {
+                Ok(Role::Dispatcher) => auth_service.get_dispatcher_by_user_id(user.id).await?,
+                _ => None,
+            };
+
+            let tow_truck_id = match user.role.parse::<Role>() {
+                Ok(Role::Driver) => {
+                    let tow_truck_service = req
+                        .app_data::<web::Data<
+                            TowTruckService<
+                                TowTruckRepositoryImpl,
+                                OrderRepositoryImpl,
+                                MapRepositoryImpl,
+                            >,
+                        >>()
+                        .ok_or(AppError::InternalServerError)?;
+                    tow_truck_service
+                        .get_tow_truck_id_by_driver_id(user.id)
+                        .await?
+                }
+                _ => None,
+            };
+
+            Ok(AuthenticatedUser {
+                user,
+                dispatcher,
+                tow_truck_id,
+            })
+        })
+    }
+}
@@ -0,0 +1,184 @@
+// 割り当て問題をハンガリアン法で解く。
+// costs[i][j] は行 i を列 j に割り当てるコストで、行数は列数以下でなければならない。
+// 各行に割り当てた列を返す。コストの総和が最小になり、同じ列は 2 度使われない。
+pub fn solve_assignment(costs: &[Vec<i64>]) -> Vec<usize> {
+    let rows = costs.len();
+    if rows == 0 {
+        return Vec::new();
+    }
+    let columns = costs[0].len();
+    assert!(rows <= columns, "行数は列数以下でなければならない");
+
+    // 添字 0 は番兵として使うため、行・列とも 1 始まりで扱う
+    let mut row_potentials = vec![0i64; rows + 1];
+    let mut column_potentials = vec![0i64; columns + 1];
+    let mut matched_rows = vec![0usize; columns + 1];
+    let mut previous_columns = vec![0usize; columns + 1];
+
+    for row in 1..=rows {
+        matched_rows[0] = row;
+        let mut current_column = 0;
+        let mut min_slacks = vec![i64::MAX; columns + 1];
+        let mut visited = vec![false; columns + 1];
+
+        // 増加路が見つかるまでポテンシャルを調整しながら列を訪れる
+        loop {
+            visited[current_column] = true;
+            let current_row = matched_rows[current_column];
+            let mut delta = i64::MAX;
+            let mut next_column = 0;
+
+            for column in 1..=columns {
+                if visited[column] {
+                    continue;
+                }
+                let slack = costs[current_row - 1][column - 1]
+                    - row_potentials[current_row]
+                    - column_potentials[column];
+                if slack < min_slacks[column] {
+                    min_slacks[column] = slack;
+                    previous_columns[column] = current_column;
+                }
+                if min_slacks[column] < delta {
+                    delta = min_slacks[column];
+                    next_column = column;
+                }
+            }
+
+            for column in 0..=columns {
+                if visited[column] {
+                    row_potentials[matched_rows[column]] += delta;
+                    column_potentials[column] -= delta;
+                } else {
+                    min_slacks[column] -= delta;
+                }
+            }
+
+            current_column = next_column;
+            if matched_rows[current_column] == 0 {
+                break;
+            }
+        }
+
+        // 見つけた増加路に沿って割り当てを入れ替える
+        while current_column != 0 {
+            let previous_column = previous_columns[current_column];
+            matched_rows[current_column] = matched_rows[previous_column];
+            current_column = previous_column;
+        }
+    }
+
+    let mut assignment = vec![0; rows];
+    for column in 1..=columns {
+        if matched_rows[column] != 0 {
+            assignment[matched_rows[column] - 1] = column - 1;
+        }
+    }
+    assignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total_cost(costs: &[Vec<i64>], assignment: &[usize]) -> i64 {
+        assignment
+            .iter()
+            .enumerate()
+            .map(|(row, &column)| costs[row][column])
+            .sum()
+    }
+
+    // 全探索で求めた最小コスト
+    fn brute_force_cost(costs: &[Vec<i64>]) -> i64 {
+        fn search(costs: &[Vec<i64>], row: usize, used: &mut Vec<bool>) -> i64 {
+            if row == costs.len() {
+                return 0;
+            }
+            let mut best = i64::MAX;
+            for column in 0..used.len() {
+                if used[column] {
+                    continue;
+                }
+                used[column] = true;
+                best = best.min(costs[row][column] + search(costs, row + 1, used));
+                used[column] = false;
+            }
+            best
+        }
+        search(costs, 0, &mut vec![false; costs[0].len()])
+    }
+
+    fn assert_distinct_columns(assignment: &[usize]) {
+        let mut columns = assignment.to_vec();
+        columns.sort_unstable();
+        columns.dedup();
+        assert_eq!(columns.len(), assignment.len());
+    }
+
+    #[test]
+    fn empty_matrix() {
+        assert!(solve_assignment(&[]).is_empty());
+    }
+
+    #[test]
+    fn square_matrix() {
+        let costs = vec![vec![4, 1, 3], vec![2, 0, 5], vec![3, 2, 2]];
+        assert_eq!(solve_assignment(&costs), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn avoids_greedy_choice() {
+        // 行 0 が最小の列 0 を取ると、行 1 に大きなコストがかかる
+        let costs = vec![vec![1, 2], vec![1, 100]];
+        assert_eq!(solve_assignment(&costs), vec![1, 0]);
+    }
+
+    #[test]
+    fn rectangular_matrix() {
+        let costs = vec![vec![5, 9, 1, 7], vec![6, 2, 8, 3]];
+        assert_eq!(solve_assignment(&costs), vec![2, 1]);
+
+        let costs = vec![vec![10, 1, 10], vec![1, 10, 10]];
+        assert_eq!(solve_assignment(&costs), vec![1, 0]);
+    }
+
+    #[test]
+    fn all_equal_costs() {
+        let costs = vec![vec![7; 4]; 3];
+        let assignment = solve_assignment(&costs);
+        assert_eq!(assignment.len(), 3);
+        assert_distinct_columns(&assignment);
+        assert_eq!(total_cost(&costs, &assignment), 21);
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        // 再現できるように線形合同法で行列を作る
+        let mut seed: u64 = 42;
+        let mut next = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as i64
+        };
+        for _ in 0..200 {
+            let rows = (next() % 5 + 1) as usize;
+            let columns = rows + (next() % 3) as usize;
+            let costs: Vec<Vec<i64>> = (0..rows)
+                .map(|_| (0..columns).map(|_| next() % 20).collect())
+                .collect();
+
+            let assignment = solve_assignment(&costs);
+            assert_eq!(assignment.len(), rows);
+            assert_distinct_columns(&assignment);
+            assert_eq!(total_cost(&costs, &assignment), brute_force_cost(&costs));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn more_rows_than_columns() {
+        solve_assignment(&[vec![1], vec![2]]);
+    }
+}
@@ -0,0 +1,49 @@
+use std::str::FromStr;
+
+use sqlx::FromRow;
+
+use crate::errors::AppError;
+
+// 自動配車で先に割り当てる依頼の順序
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DispatchPriority {
+    // 依頼日時の古い順
+    OrderTime,
+    // 車の価値の高い順
+    CarValue,
+}
+
+impl DispatchPriority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DispatchPriority::OrderTime => "order_time",
+            DispatchPriority::CarValue => "car_value",
+        }
+    }
+
+    pub fn sort_order(&self) -> &'static str {
+        match self {
+            DispatchPriority::OrderTime => "ASC",
+            DispatchPriority::CarValue => "DESC",
+        }
+    }
+}
+
+impl FromStr for DispatchPriority {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "order_time" => Ok(DispatchPriority::OrderTime),
+            "car_value" => Ok(DispatchPriority::CarValue),
+            _ => Err(AppError::BadRequest),
+        }
+    }
+}
+
+#[derive(FromRow, Clone, Debug)]
+pub struct AutoDispatchSetting {
+    pub area_id: i32,
+    pub enabled: bool,
+    pub priority: String,
+}
@@ -1,7 +1,25 @@
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::cmp::Ordering;
 use sqlx::FromRow;
 
+// 辺の重みは移動にかかる時間を表す。重みは辺の両端の座標の直線距離の 1〜1.5 倍になっているため、
+// 座標の 1 単位を 1 m、レッカー車の速さを秒速 10 m (時速 36 km) とみなし、重み 10 を 1 秒とする。
+pub const WEIGHT_PER_SECOND: i64 = 10;
+// これより遠いレッカー車は配車の候補にしない
+pub const MAX_DISPATCH_DISTANCE: i32 = 10000000;
+// グラフはエリアごとにプロセス内で共有されるため、最短経路のキャッシュはこの件数で打ち止めにする
+const MAX_CACHED_DISTANCES: usize = 100_000;
+
+// 重みの合計 (距離) を移動にかかる秒数に換算する。端数は切り上げる。
+pub fn travel_seconds(distance: i32) -> i64 {
+    (distance as i64 + WEIGHT_PER_SECOND - 1) / WEIGHT_PER_SECOND
+}
+
+// seconds 秒の間に移動できる距離
+pub fn travel_distance(seconds: i64) -> i64 {
+    seconds.saturating_mul(WEIGHT_PER_SECOND)
+}
+
 #[derive(FromRow, Clone, Debug)]
 pub struct Node {
     pub id: i32,
@@ -16,6 +34,20 @@ pub struct Edge {
     pub weight: i32,
 }
 
+#[derive(Clone, Debug)]
+pub struct RouteStep {
+    pub node_id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub weight: i32, // 直前のノードからこのノードまでの辺の重み (始点は 0)
+}
+
+#[derive(Clone, Debug)]
+pub struct Route {
+    pub distance: i32,
+    pub steps: Vec<RouteStep>,
+}
+
 #[derive(Debug)]
 pub struct Graph {
     pub nodes: HashMap<i32, Node>,
@@ -72,6 +104,32 @@ impl Graph {
             .push(reverse_edge);
     }
 
+    // 辺の重みを両方向とも書き換え、距離のキャッシュを破棄する
+    pub fn update_edge_weight(&mut self, node_a_id: i32, node_b_id: i32, weight: i32) -> bool {
+        let mut updated = false;
+        for (from, to) in [(node_a_id, node_b_id), (node_b_id, node_a_id)] {
+            if let Some(edges) = self.edges.get_mut(&from) {
+                for edge in edges.iter_mut().filter(|edge| edge.node_b_id == to) {
+                    edge.weight = weight;
+                    updated = true;
+                }
+            }
+        }
+
+        if updated {
+            self.cache.clear();
+        }
+        updated
+    }
+
+    // 上限に達したら全件を捨てる。よく使われる経路はすぐに載り直す。
+    fn cache_distance(&mut self, from_node_id: i32, to_node_id: i32, distance: i32) {
+        if self.cache.len() >= MAX_CACHED_DISTANCES {
+            self.cache.clear();
+        }
+        self.cache.insert((from_node_id, to_node_id), distance);
+    }
+
     pub fn shortest_path(&mut self, from_node_id: i32, to_node_id: i32) -> i32 {
         if let Some(&cached_distance) = self.cache.get(&(from_node_id, to_node_id)) {
             return cached_distance;
@@ -85,7 +143,7 @@ impl Graph {
 
         while let Some(State { cost, position }) = heap.pop() {
             if position == to_node_id {
-                self.cache.insert((from_node_id, to_node_id), cost);
+                self.cache_distance(from_node_id, to_node_id, cost);
                 return cost;
             }
 
@@ -108,8 +166,306 @@ impl Graph {
             }
         }
 
-        self.cache.insert((from_node_id, to_node_id), i32::MAX);
+        self.cache_distance(from_node_id, to_node_id, i32::MAX);
         i32::MAX
     }
+
+    // from_node_id から targets に含まれるノードまでの最短距離を近い順に返す。
+    // limit 個のノードが確定した時点で探索を打ち切る (同じ距離のノードは含める)。
+    pub fn nearest_targets(
+        &mut self,
+        from_node_id: i32,
+        targets: &HashSet<i32>,
+        limit: usize,
+    ) -> Vec<(i32, i32)> {
+        let mut found: Vec<(i32, i32)> = Vec::new();
+        if limit == 0 || targets.is_empty() {
+            return found;
+        }
+
+        let mut distances = HashMap::new();
+        let mut heap = BinaryHeap::new();
+
+        distances.insert(from_node_id, 0);
+        heap.push(State { cost: 0, position: from_node_id });
+
+        while let Some(State { cost, position }) = heap.pop() {
+            if cost > *distances.get(&position).unwrap_or(&i32::MAX) {
+                continue;
+            }
+
+            if let Some(&(_, last_distance)) = found.last() {
+                if found.len() >= limit && cost > last_distance {
+                    break;
+                }
+            }
+
+            if targets.contains(&position) {
+                // 無向グラフなので、ターゲットから出発した場合と同じ距離になる
+                self.cache_distance(position, from_node_id, cost);
+                found.push((position, cost));
+                if found.len() == targets.len() {
+                    break;
+                }
+            }
+
+            if let Some(edges) = self.edges.get(&position) {
+                for edge in edges {
+                    let next = State {
+                        cost: cost + edge.weight,
+                        position: edge.node_b_id,
+                    };
+
+                    if next.cost < *distances.get(&next.position).unwrap_or(&i32::MAX) {
+                        heap.push(next);
+                        distances.insert(next.position, next.cost);
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    // sources のいずれかから max_distance 以内で到達できるノードと、最も近い source からの距離を返す
+    pub fn distances_within(&self, sources: &HashSet<i32>, max_distance: i32) -> HashMap<i32, i32> {
+        let mut distances = HashMap::new();
+        let mut heap = BinaryHeap::new();
+
+        for &source in sources {
+            distances.insert(source, 0);
+            heap.push(State { cost: 0, position: source });
+        }
+
+        while let Some(State { cost, position }) = heap.pop() {
+            if cost > *distances.get(&position).unwrap_or(&i32::MAX) {
+                continue;
+            }
+
+            if let Some(edges) = self.edges.get(&position) {
+                for edge in edges {
+                    let next = State {
+                        cost: cost + edge.weight,
+                        position: edge.node_b_id,
+                    };
+
+                    if next.cost <= max_distance
+                        && next.cost < *distances.get(&next.position).unwrap_or(&i32::MAX)
+                    {
+                        heap.push(next);
+                        distances.insert(next.position, next.cost);
+                    }
+                }
+            }
+        }
+
+        distances
+    }
+
+    pub fn shortest_route(&self, from_node_id: i32, to_node_id: i32) -> Option<Route> {
+        let mut distances = HashMap::new();
+        let mut previous: HashMap<i32, (i32, i32)> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+
+        distances.insert(from_node_id, 0);
+        heap.push(State { cost: 0, position: from_node_id });
+
+        while let Some(State { cost, position }) = heap.pop() {
+            if position == to_node_id {
+                return self.build_route(from_node_id, to_node_id, cost, &previous);
+            }
+
+            if cost > *distances.get(&position).unwrap_or(&i32::MAX) {
+                continue;
+            }
+
+            if let Some(edges) = self.edges.get(&position) {
+                for edge in edges {
+                    let next = State {
+                        cost: cost + edge.weight,
+                        position: edge.node_b_id,
+                    };
+
+                    if next.cost < *distances.get(&next.position).unwrap_or(&i32::MAX) {
+                        heap.push(next);
+                        distances.insert(next.position, next.cost);
+                        previous.insert(next.position, (position, edge.weight));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn build_route(
+        &self,
+        from_node_id: i32,
+        to_node_id: i32,
+        distance: i32,
+        previous: &HashMap<i32, (i32, i32)>,
+    ) -> Option<Route> {
+        let mut path = vec![(to_node_id, 0)];
+        let mut current = to_node_id;
+        while current != from_node_id {
+            let &(prev_node_id, weight) = previous.get(&current)?;
+            path.last_mut()?.1 = weight;
+            path.push((prev_node_id, 0));
+            current = prev_node_id;
+        }
+        path.reverse();
+
+        let steps = path
+            .into_iter()
+            .map(|(node_id, weight)| {
+                let node = self.nodes.get(&node_id)?;
+                Some(RouteStep {
+                    node_id,
+                    x: node.x,
+                    y: node.y,
+                    weight,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Route { distance, steps })
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 - 2 - 3 は重み 1、1 - 3 は近道に見えて重み 5、3 - 4 は重み 2。5 はどこにもつながっていない。
+    fn sample_graph() -> Graph {
+        let mut graph = Graph::new();
+        for id in 1..=5 {
+            graph.add_node(Node {
+                id,
+                x: id * 10,
+                y: 0,
+            });
+        }
+        for (node_a_id, node_b_id, weight) in [(1, 2, 1), (2, 3, 1), (1, 3, 5), (3, 4, 2)] {
+            graph.add_edge(Edge {
+                node_a_id,
+                node_b_id,
+                weight,
+            });
+        }
+        graph
+    }
+
+    #[test]
+    fn finds_shortest_path() {
+        let mut graph = sample_graph();
+
+        assert_eq!(graph.shortest_path(1, 3), 2);
+        assert_eq!(graph.shortest_path(4, 1), 4);
+        assert_eq!(graph.shortest_path(1, 5), i32::MAX);
+        assert_eq!(graph.cache.get(&(1, 3)), Some(&2));
+    }
+
+    #[test]
+    fn clears_cache_when_edge_weight_changes() {
+        let mut graph = sample_graph();
+        graph.shortest_path(1, 3);
+
+        assert!(graph.update_edge_weight(3, 1, 1));
+        assert!(graph.cache.is_empty());
+        assert_eq!(graph.shortest_path(1, 3), 1);
+        assert!(!graph.update_edge_weight(1, 4, 1));
+    }
+
+    #[test]
+    fn finds_nearest_targets_in_order() {
+        let mut graph = sample_graph();
+        let targets = HashSet::from([3, 4, 5]);
+
+        assert_eq!(graph.nearest_targets(1, &targets, 1), vec![(3, 2)]);
+        assert_eq!(graph.nearest_targets(1, &targets, 5), vec![(3, 2), (4, 4)]);
+        assert!(graph.nearest_targets(1, &targets, 0).is_empty());
+        // ターゲットからの距離としてキャッシュされる
+        assert_eq!(graph.cache.get(&(4, 1)), Some(&4));
+    }
+
+    #[test]
+    fn includes_targets_tied_with_the_last_one() {
+        let mut graph = sample_graph();
+        graph.add_node(Node { id: 6, x: 0, y: 10 });
+        graph.add_edge(Edge {
+            node_a_id: 1,
+            node_b_id: 6,
+            weight: 1,
+        });
+
+        let mut nearest = graph.nearest_targets(1, &HashSet::from([2, 6, 4]), 1);
+        nearest.sort();
+        assert_eq!(nearest, vec![(2, 1), (6, 1)]);
+    }
+
+    #[test]
+    fn finds_distances_within_limit_from_nearest_source() {
+        let graph = sample_graph();
+
+        assert_eq!(
+            graph.distances_within(&HashSet::from([1]), 2),
+            HashMap::from([(1, 0), (2, 1), (3, 2)])
+        );
+        assert_eq!(
+            graph.distances_within(&HashSet::from([1, 4]), 2),
+            HashMap::from([(1, 0), (2, 1), (3, 2), (4, 0)])
+        );
+    }
+
+    #[test]
+    fn builds_shortest_route_with_step_weights() {
+        let graph = sample_graph();
+
+        let route = graph.shortest_route(1, 4).unwrap();
+        assert_eq!(route.distance, 4);
+        let steps: Vec<(i32, i32, i32)> = route
+            .steps
+            .iter()
+            .map(|step| (step.node_id, step.x, step.weight))
+            .collect();
+        assert_eq!(steps, vec![(1, 10, 0), (2, 20, 1), (3, 30, 1), (4, 40, 2)]);
+    }
+
+    #[test]
+    fn returns_single_step_route_to_the_same_node() {
+        let graph = sample_graph();
+
+        let route = graph.shortest_route(2, 2).unwrap();
+        assert_eq!(route.distance, 0);
+        assert_eq!(route.steps.len(), 1);
+        assert_eq!(route.steps[0].node_id, 2);
+    }
+
+    #[test]
+    fn returns_no_route_to_unreachable_node() {
+        assert!(sample_graph().shortest_route(1, 5).is_none());
+    }
+
+    #[test]
+    fn rounds_travel_time_up_to_whole_seconds() {
+        assert_eq!(travel_seconds(0), 0);
+        assert_eq!(travel_seconds(1), 1);
+        assert_eq!(travel_seconds(WEIGHT_PER_SECOND as i32), 1);
+        assert_eq!(travel_seconds(5187), 519);
+    }
+
+    #[test]
+    fn clears_cache_when_full() {
+        let mut graph = Graph::new();
+        graph.add_node(Node { id: 1, x: 0, y: 0 });
+        for to_node_id in 0..MAX_CACHED_DISTANCES as i32 {
+            graph.shortest_path(1, -to_node_id);
+        }
+        assert_eq!(graph.cache.len(), MAX_CACHED_DISTANCES);
+
+        graph.shortest_path(1, 1);
+        assert_eq!(graph.cache.len(), 1);
+        assert_eq!(graph.cache.get(&(1, 1)), Some(&0));
+    }
+}
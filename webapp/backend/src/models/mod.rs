@@ -1,4 +1,7 @@
+pub mod assignment;
+pub mod dispatch;
 pub mod graph;
 pub mod order;
+pub mod profile_image;
 pub mod tow_truck;
 pub mod user;
@@ -1,17 +1,81 @@
+use std::str::FromStr;
+
 use chrono::{DateTime, Utc};
 use sqlx::FromRow;
 
+use crate::errors::AppError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderStatus {
+    Pending,
+    Dispatched,
+    EnRoute,
+    Arrived,
+    Towing,
+    Completed,
+    Cancelled,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Dispatched => "dispatched",
+            OrderStatus::EnRoute => "en_route",
+            OrderStatus::Arrived => "arrived",
+            OrderStatus::Towing => "towing",
+            OrderStatus::Completed => "completed",
+            OrderStatus::Cancelled => "cancelled",
+        }
+    }
+
+    // 状態遷移表。キャンセルできるのは、レッカー車が向かい始める前 (pending, dispatched) まで。
+    pub fn next_statuses(&self) -> &'static [OrderStatus] {
+        match self {
+            OrderStatus::Pending => &[OrderStatus::Dispatched, OrderStatus::Cancelled],
+            OrderStatus::Dispatched => &[OrderStatus::EnRoute, OrderStatus::Cancelled],
+            OrderStatus::EnRoute => &[OrderStatus::Arrived],
+            OrderStatus::Arrived => &[OrderStatus::Towing],
+            OrderStatus::Towing => &[OrderStatus::Completed],
+            OrderStatus::Completed | OrderStatus::Cancelled => &[],
+        }
+    }
+
+    pub fn can_transition_to(&self, next: OrderStatus) -> bool {
+        self.next_statuses().contains(&next)
+    }
+
+    // この状態になった時点で、割り当てられていたレッカー車は空きに戻る
+    pub fn releases_tow_truck(&self) -> bool {
+        matches!(self, OrderStatus::Completed | OrderStatus::Cancelled)
+    }
+}
+
+impl FromStr for OrderStatus {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(OrderStatus::Pending),
+            "dispatched" => Ok(OrderStatus::Dispatched),
+            "en_route" => Ok(OrderStatus::EnRoute),
+            "arrived" => Ok(OrderStatus::Arrived),
+            "towing" => Ok(OrderStatus::Towing),
+            "completed" => Ok(OrderStatus::Completed),
+            "cancelled" => Ok(OrderStatus::Cancelled),
+            _ => Err(AppError::BadRequest),
+        }
+    }
+}
+
 #[derive(FromRow, Clone, Debug)]
 pub struct Order {
     pub id: i32,
     pub client_id: i32,
-    pub dispatcher_id: Option<i32>,
     pub tow_truck_id: Option<i32>,
     pub status: String,
     pub node_id: i32,
     pub car_value: f64,
-    pub order_time: DateTime<Utc>,
-    pub completed_time: Option<DateTime<Utc>>,
 }
 
 #[derive(FromRow, Clone, Debug)]
@@ -23,3 +87,62 @@ pub struct CompletedOrder {
     pub completed_time: DateTime<Utc>,
     pub car_value: f64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_STATUSES: [OrderStatus; 7] = [
+        OrderStatus::Pending,
+        OrderStatus::Dispatched,
+        OrderStatus::EnRoute,
+        OrderStatus::Arrived,
+        OrderStatus::Towing,
+        OrderStatus::Completed,
+        OrderStatus::Cancelled,
+    ];
+
+    #[test]
+    fn follows_the_transition_table() {
+        use OrderStatus::*;
+
+        let allowed = [
+            (Pending, Dispatched),
+            (Pending, Cancelled),
+            (Dispatched, EnRoute),
+            (Dispatched, Cancelled),
+            (EnRoute, Arrived),
+            (Arrived, Towing),
+            (Towing, Completed),
+        ];
+        for current in ALL_STATUSES {
+            for next in ALL_STATUSES {
+                assert_eq!(
+                    current.can_transition_to(next),
+                    allowed.contains(&(current, next)),
+                    "{:?} -> {:?}",
+                    current,
+                    next
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn releases_tow_truck_only_when_finished() {
+        for status in ALL_STATUSES {
+            assert_eq!(
+                status.releases_tow_truck(),
+                matches!(status, OrderStatus::Completed | OrderStatus::Cancelled)
+            );
+        }
+    }
+
+    #[test]
+    fn parses_what_it_prints() {
+        for status in ALL_STATUSES {
+            assert_eq!(status.as_str().parse::<OrderStatus>().unwrap(), status);
+        }
+        assert!("unknown".parse::<OrderStatus>().is_err());
+    }
+}
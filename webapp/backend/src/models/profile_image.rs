@@ -0,0 +1,114 @@
+use crate::errors::AppError;
+
+// プロフィール画像として配信できるサイズ (幅, 高さ)
+pub const ALLOWED_PROFILE_IMAGE_SIZES: &[(u32, u32)] =
+    &[(64, 64), (128, 128), (256, 256), (500, 500)];
+const DEFAULT_PROFILE_IMAGE_SIZE: (u32, u32) = (500, 500);
+
+// アップロードできるプロフィール画像の上限
+pub const MAX_PROFILE_IMAGE_UPLOAD_BYTES: usize = 5 * 1024 * 1024;
+pub const MIN_PROFILE_IMAGE_DIMENSION: u32 = 16;
+pub const MAX_PROFILE_IMAGE_DIMENSION: u32 = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileImageFormat {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+impl ProfileImageFormat {
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            "image/png" => Some(ProfileImageFormat::Png),
+            "image/jpeg" => Some(ProfileImageFormat::Jpeg),
+            "image/webp" => Some(ProfileImageFormat::WebP),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ProfileImageFormat::Png => "png",
+            ProfileImageFormat::Jpeg => "jpg",
+            ProfileImageFormat::WebP => "webp",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ProfileImageFormat::Png => "image/png",
+            ProfileImageFormat::Jpeg => "image/jpeg",
+            ProfileImageFormat::WebP => "image/webp",
+        }
+    }
+
+    pub fn image_format(&self) -> image::ImageFormat {
+        match self {
+            ProfileImageFormat::Png => image::ImageFormat::Png,
+            ProfileImageFormat::Jpeg => image::ImageFormat::Jpeg,
+            ProfileImageFormat::WebP => image::ImageFormat::WebP,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProfileImageVariant {
+    pub width: u32,
+    pub height: u32,
+    pub format: ProfileImageFormat,
+}
+
+impl Default for ProfileImageVariant {
+    fn default() -> Self {
+        ProfileImageVariant {
+            width: DEFAULT_PROFILE_IMAGE_SIZE.0,
+            height: DEFAULT_PROFILE_IMAGE_SIZE.1,
+            format: ProfileImageFormat::Png,
+        }
+    }
+}
+
+impl ProfileImageVariant {
+    // 幅だけ、または高さだけが指定された場合は正方形とみなす
+    pub fn new(
+        width: Option<u32>,
+        height: Option<u32>,
+        format: Option<&str>,
+    ) -> Result<Self, AppError> {
+        let (width, height) = match (width, height) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(size), None) | (None, Some(size)) => (size, size),
+            (None, None) => (
+                ProfileImageVariant::default().width,
+                ProfileImageVariant::default().height,
+            ),
+        };
+        if !ALLOWED_PROFILE_IMAGE_SIZES.contains(&(width, height)) {
+            return Err(AppError::BadRequest);
+        }
+
+        let format = match format {
+            None | Some("png") => ProfileImageFormat::Png,
+            Some("jpeg") | Some("jpg") => ProfileImageFormat::Jpeg,
+            Some("webp") => ProfileImageFormat::WebP,
+            Some(_) => return Err(AppError::BadRequest),
+        };
+
+        Ok(ProfileImageVariant {
+            width,
+            height,
+            format,
+        })
+    }
+
+    pub fn file_name(&self, image_name: &str) -> String {
+        format!(
+            "{}_{}x{}.{}",
+            image_name,
+            self.width,
+            self.height,
+            self.format.extension()
+        )
+    }
+}
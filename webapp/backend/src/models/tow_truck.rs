@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use sqlx::FromRow;
 
 #[derive(FromRow, Clone, Debug)]
@@ -9,3 +10,20 @@ pub struct TowTruck {
     pub area_id: i32,
     pub node_id: i32,
 }
+
+// 位置情報の更新を検証するための現在地。位置情報が一度も記録されていなければ node_id は None になる。
+#[derive(FromRow, Clone, Debug)]
+pub struct TowTruckPosition {
+    pub id: i32,
+    pub area_id: i32,
+    pub node_id: Option<i32>,
+    pub location_updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow, Clone, Debug)]
+pub struct TowTruckLocation {
+    pub node_id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub timestamp: DateTime<Utc>,
+}
@@ -1,33 +1,61 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
 use sqlx::FromRow;
 
+use crate::errors::AppError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Dispatcher,
+    Driver,
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(Role::Client),
+            "dispatcher" => Ok(Role::Dispatcher),
+            "driver" => Ok(Role::Driver),
+            "admin" => Ok(Role::Admin),
+            _ => Err(AppError::BadRequest),
+        }
+    }
+}
+
 #[derive(FromRow, Clone, Debug)]
 pub struct User {
     pub id: i32,
     pub username: String,
     pub password: String,
-    pub profile_image: String,
     pub role: String,
 }
 
-#[derive(FromRow, Clone, Debug)]
-pub struct Session {
-    pub id: i32,
-    pub user_id: i32,
-    pub session_token: String,
-    pub is_valid: bool,
+impl User {
+    pub fn has_any_role(&self, roles: &[Role]) -> bool {
+        match self.role.parse::<Role>() {
+            Ok(role) => roles.contains(&role),
+            Err(_) => false,
+        }
+    }
 }
 
 #[derive(FromRow, Clone, Debug)]
-pub struct Driver {
+pub struct Session {
     pub id: i32,
     pub user_id: i32,
     pub session_token: String,
     pub is_valid: bool,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
 }
 
 #[derive(FromRow, Clone, Debug)]
 pub struct Dispatcher {
     pub id: i32,
-    pub user_id: i32,
     pub area_id: i32,
 }
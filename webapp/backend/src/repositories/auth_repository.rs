@@ -1,6 +1,7 @@
 use crate::errors::AppError;
 use crate::models::user::{Dispatcher, User};
 use crate::{domains::auth_service::AuthRepository, models::user::Session};
+use chrono::{DateTime, Utc};
 use sqlx::mysql::MySqlPool;
 
 #[derive(Debug)]
@@ -45,17 +46,6 @@ impl AuthRepository for AuthRepositoryImpl {
         Ok(profile_image_name)
     }
 
-    async fn authenticate_user(&self, username: &str, password: &str) -> Result<User, AppError> {
-        let user =
-            sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = ? AND password = ?")
-                .bind(username)
-                .bind(password)
-                .fetch_one(&self.pool)
-                .await?;
-
-        Ok(user)
-    }
-
     async fn create_user(
         &self,
         username: &str,
@@ -72,10 +62,61 @@ impl AuthRepository for AuthRepositoryImpl {
         Ok(())
     }
 
-    async fn create_session(&self, user_id: i32, session_token: &str) -> Result<(), AppError> {
-        sqlx::query("INSERT INTO sessions (user_id, session_token) VALUES (?, ?)")
+    async fn update_password(&self, user_id: i32, password: &str) -> Result<(), AppError> {
+        sqlx::query("UPDATE users SET password = ? WHERE id = ?")
+            .bind(password)
             .bind(user_id)
-            .bind(session_token)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_profile_image(
+        &self,
+        user_id: i32,
+        profile_image_name: &str,
+    ) -> Result<(), AppError> {
+        sqlx::query("UPDATE users SET profile_image = ? WHERE id = ?")
+            .bind(profile_image_name)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn create_session(
+        &self,
+        user_id: i32,
+        session_token: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        let now = Utc::now();
+        sqlx::query(
+            "INSERT INTO sessions (user_id, session_token, created_at, last_seen_at, expires_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(user_id)
+        .bind(session_token)
+        .bind(now)
+        .bind(now)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn renew_session(
+        &self,
+        session_id: i32,
+        last_seen_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        sqlx::query("UPDATE sessions SET last_seen_at = ?, expires_at = ? WHERE id = ?")
+            .bind(last_seen_at)
+            .bind(expires_at)
+            .bind(session_id)
             .execute(&self.pool)
             .await?;
 
@@ -91,6 +132,24 @@ impl AuthRepository for AuthRepositoryImpl {
         Ok(())
     }
 
+    async fn delete_sessions_by_user_id(&self, user_id: i32) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM sessions WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64, AppError> {
+        let result = sqlx::query("DELETE FROM sessions WHERE expires_at <= ?")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
     async fn find_session_by_session_token(
         &self,
         session_token: &str,
@@ -125,14 +184,4 @@ impl AuthRepository for AuthRepositoryImpl {
 
         Ok(dispatcher)
     }
-
-    async fn create_dispatcher(&self, user_id: i32, area_id: i32) -> Result<(), AppError> {
-        sqlx::query("INSERT INTO dispatchers (user_id, area_id) VALUES (?, ?)")
-            .bind(user_id)
-            .bind(area_id)
-            .execute(&self.pool)
-            .await?;
-
-        Ok(())
-    }
 }
@@ -0,0 +1,88 @@
+use crate::domains::dispatch_service::DispatchRepository;
+use crate::errors::AppError;
+use crate::models::dispatch::AutoDispatchSetting;
+use sqlx::mysql::MySqlPool;
+
+// 自動配車を記録するシステムユーザーのユーザー名
+const SYSTEM_DISPATCHER_USERNAME: &str = "system";
+
+#[derive(Debug)]
+pub struct DispatchRepositoryImpl {
+    pool: MySqlPool,
+}
+
+impl DispatchRepositoryImpl {
+    pub fn new(pool: MySqlPool) -> Self {
+        DispatchRepositoryImpl { pool }
+    }
+}
+
+impl DispatchRepository for DispatchRepositoryImpl {
+    async fn find_auto_dispatch_setting(
+        &self,
+        area_id: i32,
+    ) -> Result<Option<AutoDispatchSetting>, AppError> {
+        let setting = sqlx::query_as::<_, AutoDispatchSetting>(
+            "SELECT area_id, enabled, priority FROM auto_dispatch_settings WHERE area_id = ?",
+        )
+        .bind(area_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(setting)
+    }
+
+    async fn get_enabled_auto_dispatch_settings(
+        &self,
+    ) -> Result<Vec<AutoDispatchSetting>, AppError> {
+        let settings = sqlx::query_as::<_, AutoDispatchSetting>(
+            "SELECT area_id, enabled, priority FROM auto_dispatch_settings WHERE enabled = TRUE",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(settings)
+    }
+
+    async fn upsert_auto_dispatch_setting(
+        &self,
+        area_id: i32,
+        enabled: bool,
+        priority: &str,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "INSERT INTO auto_dispatch_settings (area_id, enabled, priority) VALUES (?, ?, ?)
+            ON DUPLICATE KEY UPDATE enabled = VALUES(enabled), priority = VALUES(priority)",
+        )
+        .bind(area_id)
+        .bind(enabled)
+        .bind(priority)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_system_dispatcher_id(&self, area_id: i32) -> Result<Option<i32>, AppError> {
+        let dispatcher_id = sqlx::query_scalar(
+            "SELECT
+                d.id
+            FROM
+                dispatchers d
+            JOIN
+                users u
+            ON
+                d.user_id = u.id
+            WHERE
+                u.username = ?
+            AND
+                d.area_id = ?",
+        )
+        .bind(SYSTEM_DISPATCHER_USERNAME)
+        .bind(area_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(dispatcher_id)
+    }
+}
@@ -1,4 +1,5 @@
 pub mod auth_repository;
+pub mod dispatch_repository;
 pub mod map_repository;
 pub mod order_repository;
 pub mod tow_truck_repository;
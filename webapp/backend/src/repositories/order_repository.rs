@@ -34,108 +34,64 @@ impl OrderRepository for OrderRepositoryImpl {
         Ok(order)
     }
 
-    async fn update_order_status(&self, order_id: i32, status: &str) -> Result<(), AppError> {
-        sqlx::query("UPDATE orders SET status = ? WHERE id = ?")
-            .bind(status)
-            .bind(order_id)
-            .execute(&self.pool)
-            .await?;
+    async fn find_active_order_by_tow_truck_id(
+        &self,
+        tow_truck_id: i32,
+    ) -> Result<Option<Order>, AppError> {
+        let order = sqlx::query_as::<_, Order>(
+            "SELECT
+                *
+            FROM
+                orders
+            WHERE
+                tow_truck_id = ?
+            AND
+                status IN ('dispatched', 'en_route', 'arrived', 'towing')
+            ORDER BY
+                id DESC
+            LIMIT 1",
+        )
+        .bind(tow_truck_id)
+        .fetch_optional(&self.pool)
+        .await?;
 
-        Ok(())
+        Ok(order)
     }
 
-    async fn get_paginated_orders(
+    async fn update_order_status(
         &self,
-        page: i32,
-        page_size: i32,
-        sort_by: Option<String>,
-        sort_order: Option<String>,
-        status: Option<String>,
-        area: Option<i32>,
-    ) -> Result<Vec<Order>, AppError> {
-        let offset = page * page_size;
-        let order_clause = format!(
-            "ORDER BY {} {}",
-            match sort_by.as_deref() {
-                Some("car_value") => "o.car_value",
-                Some("status") => "o.status",
-                Some("order_time") => "o.order_time",
-                _ => "o.order_time",
-            },
-            match sort_order.as_deref() {
-                Some("DESC") => "DESC",
-                Some("desc") => "DESC",
-                _ => "ASC",
-            }
-        );
+        order_id: i32,
+        current_status: &str,
+        status: &str,
+        completed_time: Option<DateTime<Utc>>,
+        release_tow_truck_id: Option<i32>,
+    ) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
 
-        let where_clause = match (status.clone(), area) {
-            (Some(_), Some(_)) => "WHERE o.status = ? AND n.area_id = ?".to_string(),
-            (None, Some(_)) => "WHERE n.area_id = ?".to_string(),
-            (Some(_), None) => "WHERE o.status = ?".to_string(),
-            _ => "".to_string(),
-        };
+        let result = sqlx::query(
+            "UPDATE orders SET status = ?, completed_time = COALESCE(?, completed_time) WHERE id = ? AND status = ?",
+        )
+        .bind(status)
+        .bind(completed_time)
+        .bind(order_id)
+        .bind(current_status)
+        .execute(&mut tx)
+        .await?;
 
-        let sql = format!(
-            "SELECT 
-                o.id, 
-                o.client_id, 
-                o.dispatcher_id, 
-                o.tow_truck_id, 
-                o.status, 
-                o.node_id, 
-                o.car_value, 
-                o.order_time, 
-                o.completed_time
-            FROM
-                orders o
-            JOIN
-                nodes n
-            ON 
-                o.node_id = n.id
-            {} 
-            {} 
-            LIMIT ? 
-            OFFSET ?",
-            where_clause, order_clause
-        );
+        if result.rows_affected() == 0 {
+            return Err(AppError::Conflict);
+        }
 
-        let orders = match (status, area) {
-            (Some(status), Some(area)) => {
-                sqlx::query_as::<_, Order>(&sql)
-                    .bind(status)
-                    .bind(area)
-                    .bind(page_size)
-                    .bind(offset)
-                    .fetch_all(&self.pool)
-                    .await?
-            }
-            (None, Some(area)) => {
-                sqlx::query_as::<_, Order>(&sql)
-                    .bind(area)
-                    .bind(page_size)
-                    .bind(offset)
-                    .fetch_all(&self.pool)
-                    .await?
-            }
-            (Some(status), None) => {
-                sqlx::query_as::<_, Order>(&sql)
-                    .bind(status)
-                    .bind(page_size)
-                    .bind(offset)
-                    .fetch_all(&self.pool)
-                    .await?
-            }
-            _ => {
-                sqlx::query_as::<_, Order>(&sql)
-                    .bind(page_size)
-                    .bind(offset)
-                    .fetch_all(&self.pool)
-                    .await?
-            }
-        };
+        if let Some(tow_truck_id) = release_tow_truck_id {
+            sqlx::query("UPDATE tow_trucks SET status = 'available' WHERE id = ?")
+                .bind(tow_truck_id)
+                .execute(&mut tx)
+                .await?;
+        }
 
-        Ok(orders)
+        tx.commit().await?;
+
+        Ok(())
     }
 
     async fn create_order(
@@ -154,36 +110,110 @@ impl OrderRepository for OrderRepositoryImpl {
         Ok(())
     }
 
-    async fn update_order_dispatched(
+    async fn dispatch_orders(
         &self,
-        id: i32,
         dispatcher_id: i32,
-        tow_truck_id: i32,
+        assignments: &[(i32, i32)],
+        completed_time: DateTime<Utc>,
     ) -> Result<(), AppError> {
-        sqlx::query(
-            "UPDATE orders SET dispatcher_id = ?, tow_truck_id = ?, status = 'dispatched' WHERE id = ?",
-        )
-        .bind(dispatcher_id)
-        .bind(tow_truck_id)
-        .bind(id)
-        .execute(&self.pool)
-        .await?;
+        let mut order_ids: Vec<i32> = assignments.iter().map(|(order_id, _)| *order_id).collect();
+        let mut tow_truck_ids: Vec<i32> = assignments
+            .iter()
+            .map(|(_, tow_truck_id)| *tow_truck_id)
+            .collect();
+        order_ids.sort_unstable();
+        tow_truck_ids.sort_unstable();
+
+        let mut tx = self.pool.begin().await?;
+
+        // デッドロックを避けるため、常に orders -> tow_trucks の順に、それぞれ ID の昇順でロックする
+        for order_id in order_ids {
+            let order_status: Option<String> =
+                sqlx::query_scalar("SELECT status FROM orders WHERE id = ? FOR UPDATE")
+                    .bind(order_id)
+                    .fetch_optional(&mut tx)
+                    .await?;
+            match order_status.as_deref() {
+                Some("pending") => {}
+                None => return Err(AppError::NotFound),
+                _ => return Err(AppError::Conflict),
+            }
+        }
+        for tow_truck_id in tow_truck_ids {
+            let tow_truck_status: Option<String> =
+                sqlx::query_scalar("SELECT status FROM tow_trucks WHERE id = ? FOR UPDATE")
+                    .bind(tow_truck_id)
+                    .fetch_optional(&mut tx)
+                    .await?;
+            match tow_truck_status.as_deref() {
+                Some("available") => {}
+                None => return Err(AppError::NotFound),
+                _ => return Err(AppError::Conflict),
+            }
+        }
+
+        for &(order_id, tow_truck_id) in assignments {
+            sqlx::query("INSERT INTO completed_orders (order_id, tow_truck_id, completed_time) VALUES (?, ?, ?)")
+                .bind(order_id)
+                .bind(tow_truck_id)
+                .bind(completed_time)
+                .execute(&mut tx)
+                .await?;
+
+            sqlx::query(
+                "UPDATE orders SET dispatcher_id = ?, tow_truck_id = ?, status = 'dispatched' WHERE id = ?",
+            )
+            .bind(dispatcher_id)
+            .bind(tow_truck_id)
+            .bind(order_id)
+            .execute(&mut tx)
+            .await?;
+
+            sqlx::query("UPDATE tow_trucks SET status = 'busy' WHERE id = ?")
+                .bind(tow_truck_id)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
 
         Ok(())
     }
 
-    async fn create_completed_order(
+    async fn cancel_order(
         &self,
         order_id: i32,
-        tow_truck_id: i32,
-        completed_time: DateTime<Utc>,
+        current_status: &str,
+        cancelled_by: i32,
+        cancelled_at: DateTime<Utc>,
+        reason: &str,
+        release_tow_truck_id: Option<i32>,
     ) -> Result<(), AppError> {
-        sqlx::query("INSERT INTO completed_orders (order_id, tow_truck_id, completed_time) VALUES (?, ?, ?)")
-            .bind(order_id)
-            .bind(tow_truck_id)
-            .bind(completed_time)
-            .execute(&self.pool)
-            .await?;
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            "UPDATE orders SET status = 'cancelled', cancelled_by = ?, cancelled_at = ?, cancel_reason = ? WHERE id = ? AND status = ?",
+        )
+        .bind(cancelled_by)
+        .bind(cancelled_at)
+        .bind(reason)
+        .bind(order_id)
+        .bind(current_status)
+        .execute(&mut tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::Conflict);
+        }
+
+        if let Some(tow_truck_id) = release_tow_truck_id {
+            sqlx::query("UPDATE tow_trucks SET status = 'available' WHERE id = ?")
+                .bind(tow_truck_id)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
 
         Ok(())
     }
@@ -249,7 +279,10 @@ async fn get_paginated_orders_with_details(
                 n.area_id,
                 o.car_value, 
                 o.order_time, 
-                o.completed_time
+                o.completed_time,
+                o.cancelled_by,
+                o.cancelled_at,
+                o.cancel_reason
             FROM
                 orders o
             LEFT JOIN
@@ -326,7 +359,10 @@ async fn get_paginated_orders_with_details(
                 n.area_id,
                 o.car_value, 
                 o.order_time, 
-                o.completed_time
+                o.completed_time,
+                o.cancelled_by,
+                o.cancelled_at,
+                o.cancel_reason
             FROM
                 orders o
             LEFT JOIN
@@ -351,3 +387,94 @@ async fn get_paginated_orders_with_details(
         Ok(order)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::db::create_pool;
+
+    // 対応を終えたレッカー車は、次の依頼にも配車できなければならない
+    #[actix_rt::test]
+    #[ignore = "DATABASE_URL に init.sql とマイグレーションを適用した MySQL が必要"]
+    async fn dispatch_same_tow_truck_twice() {
+        let pool = create_pool().await;
+        let repository = OrderRepositoryImpl::new(pool.clone());
+
+        let (dispatcher_id, area_id): (i32, i32) =
+            sqlx::query_as("SELECT id, area_id FROM dispatchers ORDER BY id LIMIT 1")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let client_id: i32 =
+            sqlx::query_scalar("SELECT id FROM users WHERE role = 'client' ORDER BY id LIMIT 1")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let driver_id: i32 =
+            sqlx::query_scalar("SELECT id FROM users WHERE role = 'driver' ORDER BY id LIMIT 1")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let node_id: i32 =
+            sqlx::query_scalar("SELECT id FROM nodes WHERE area_id = ? ORDER BY id LIMIT 1")
+                .bind(area_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+
+        let tow_truck_id = sqlx::query(
+            "INSERT INTO tow_trucks (driver_id, status, area_id) VALUES (?, 'available', ?)",
+        )
+        .bind(driver_id)
+        .bind(area_id)
+        .execute(&pool)
+        .await
+        .unwrap()
+        .last_insert_id() as i32;
+
+        let mut results = Vec::new();
+        for _ in 0..2 {
+            let order_id = sqlx::query(
+                "INSERT INTO orders (client_id, node_id, status, car_value) VALUES (?, ?, 'pending', 0)",
+            )
+            .bind(client_id)
+            .bind(node_id)
+            .execute(&pool)
+            .await
+            .unwrap()
+            .last_insert_id() as i32;
+
+            let dispatched = repository
+                .dispatch_orders(dispatcher_id, &[(order_id, tow_truck_id)], Utc::now())
+                .await;
+            let completed = repository
+                .update_order_status(
+                    order_id,
+                    "dispatched",
+                    "completed",
+                    Some(Utc::now()),
+                    Some(tow_truck_id),
+                )
+                .await;
+            results.push((dispatched, completed));
+        }
+        let status: String = sqlx::query_scalar("SELECT status FROM tow_trucks WHERE id = ?")
+            .bind(tow_truck_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+        // 依頼と completed_orders はレッカー車の削除に連動して消える
+        sqlx::query("DELETE FROM tow_trucks WHERE id = ?")
+            .bind(tow_truck_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        for (dispatched, completed) in results {
+            assert!(dispatched.is_ok(), "{:?}", dispatched);
+            assert!(completed.is_ok(), "{:?}", completed);
+        }
+        assert_eq!(status, "available");
+    }
+}
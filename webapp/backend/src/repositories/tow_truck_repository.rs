@@ -1,6 +1,7 @@
 use crate::domains::tow_truck_service::TowTruckRepository;
 use crate::errors::AppError;
-use crate::models::tow_truck::TowTruck;
+use crate::models::tow_truck::{TowTruck, TowTruckLocation, TowTruckPosition};
+use chrono::{DateTime, Utc};
 use sqlx::mysql::MySqlPool;
 
 #[derive(Debug)]
@@ -22,22 +23,6 @@ impl TowTruckRepository for TowTruckRepositoryImpl {
         status: Option<String>,
         area_id: Option<i32>,
     ) -> Result<Vec<TowTruck>, AppError> {
-        let where_clause = match (status, area_id) {
-            (Some(status), Some(area_id)) => format!(
-                "WHERE tt.status = '{}' AND tt.area_id = {} AND l.timestamp = (SELECT MAX(timestamp) FROM locations WHERE tow_truck_id = tt.id)",
-                status, area_id
-            ),
-            (None, Some(area_id)) => format!(
-                "WHERE tt.area_id = {} AND l.timestamp = (SELECT MAX(timestamp) FROM locations WHERE tow_truck_id = tt.id)",
-                area_id
-            ),
-            (Some(status), None) => format!(
-                "WHERE tt.status = '{}' AND l.timestamp = (SELECT MAX(timestamp) FROM locations WHERE tow_truck_id = tt.id)",
-                status
-            ),
-            (None, None) => "WHERE l.timestamp = (SELECT MAX(timestamp) FROM locations WHERE tow_truck_id = tt.id)"
-                .to_string(),
-        };
         let limit_clause = match page_size {
             -1 => "".to_string(),
             _ => format!("LIMIT {}", page_size),
@@ -54,47 +39,62 @@ impl TowTruckRepository for TowTruckRepositoryImpl {
                 u.username AS driver_username,
                 tt.status,
                 tt.area_id,
-                l.node_id
+                tt.node_id,
+                tt.location_updated_at
             FROM
                 tow_trucks tt
             JOIN
                 users u
             ON
                 tt.driver_id = u.id
-            JOIN 
-                locations l
-            ON 
-                tt.id = l.tow_truck_id
-            {}
+            WHERE
+                tt.node_id IS NOT NULL
+            AND
+                (? IS NULL OR tt.status = ?)
+            AND
+                (? IS NULL OR tt.area_id = ?)
             ORDER BY
                 tt.id ASC
             {}
             {}",
-            where_clause, limit_clause, offset_clause
+            limit_clause, offset_clause
         );
 
         let tow_trucks = sqlx::query_as::<_, TowTruck>(&query)
+            .bind(&status)
+            .bind(&status)
+            .bind(area_id)
+            .bind(area_id)
             .fetch_all(&self.pool)
             .await?;
 
         Ok(tow_trucks)
     }
 
+    // 走行履歴の追加と現在地の更新は同じトランザクションで行う
     async fn update_location(&self, tow_truck_id: i32, node_id: i32) -> Result<(), AppError> {
-        sqlx::query("INSERT INTO locations (tow_truck_id, node_id) VALUES (?, ?)")
+        let timestamp = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        let result =
+            sqlx::query("UPDATE tow_trucks SET node_id = ?, location_updated_at = ? WHERE id = ?")
+                .bind(node_id)
+                .bind(timestamp)
+                .bind(tow_truck_id)
+                .execute(&mut tx)
+                .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound);
+        }
+
+        sqlx::query("INSERT INTO locations (tow_truck_id, node_id, timestamp) VALUES (?, ?, ?)")
             .bind(tow_truck_id)
             .bind(node_id)
-            .execute(&self.pool)
+            .bind(timestamp)
+            .execute(&mut tx)
             .await?;
-        Ok(())
-    }
 
-    async fn update_status(&self, tow_truck_id: i32, status: &str) -> Result<(), AppError> {
-        sqlx::query("UPDATE tow_trucks SET status = ? WHERE id = ?")
-            .bind(status)
-            .bind(tow_truck_id)
-            .execute(&self.pool)
-            .await?;
+        tx.commit().await?;
 
         Ok(())
     }
@@ -102,21 +102,17 @@ impl TowTruckRepository for TowTruckRepositoryImpl {
     async fn find_tow_truck_by_id(&self, id: i32) -> Result<Option<TowTruck>, AppError> {
         let tow_truck = sqlx::query_as::<_, TowTruck>(
             "SELECT
-                tt.id, tt.driver_id, u.username AS driver_username, tt.status, l.node_id, tt.area_id
+                tt.id, tt.driver_id, u.username AS driver_username, tt.status, tt.node_id, tt.area_id
             FROM
                 tow_trucks tt
             JOIN
-                users u 
+                users u
             ON
                 tt.driver_id = u.id
-            JOIN
-                locations l
-            ON
-                tt.id = l.tow_truck_id
             WHERE
                 tt.id = ?
             AND
-                l.timestamp = (SELECT MAX(timestamp) FROM locations WHERE tow_truck_id = tt.id)",
+                tt.node_id IS NOT NULL",
         )
         .bind(id)
         .fetch_optional(&self.pool)
@@ -124,4 +120,68 @@ impl TowTruckRepository for TowTruckRepositoryImpl {
 
         Ok(tow_truck)
     }
+
+    async fn find_tow_truck_position(&self, id: i32) -> Result<Option<TowTruckPosition>, AppError> {
+        let position = sqlx::query_as::<_, TowTruckPosition>(
+            "SELECT id, area_id, node_id, location_updated_at FROM tow_trucks WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(position)
+    }
+
+    async fn find_tow_truck_id_by_driver_id(
+        &self,
+        driver_id: i32,
+    ) -> Result<Option<i32>, AppError> {
+        let tow_truck_id = sqlx::query_scalar("SELECT id FROM tow_trucks WHERE driver_id = ?")
+            .bind(driver_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(tow_truck_id)
+    }
+
+    async fn get_paginated_locations(
+        &self,
+        tow_truck_id: i32,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        page: i32,
+        page_size: i32,
+    ) -> Result<Vec<TowTruckLocation>, AppError> {
+        let locations = sqlx::query_as::<_, TowTruckLocation>(
+            "SELECT
+                l.node_id, n.x, n.y, l.timestamp
+            FROM
+                locations l
+            JOIN
+                nodes n
+            ON
+                l.node_id = n.id
+            WHERE
+                l.tow_truck_id = ?
+            AND
+                (? IS NULL OR l.timestamp >= ?)
+            AND
+                (? IS NULL OR l.timestamp <= ?)
+            ORDER BY
+                l.timestamp ASC, l.id ASC
+            LIMIT ?
+            OFFSET ?",
+        )
+        .bind(tow_truck_id)
+        .bind(from)
+        .bind(from)
+        .bind(to)
+        .bind(to)
+        .bind(page_size)
+        .bind(page * page_size)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(locations)
+    }
 }
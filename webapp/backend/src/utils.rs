@@ -1,6 +1,9 @@
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, SaltString};
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
 use sha1::{Sha1, Digest};
 use rand::Rng;
 use std::fmt::Write; // For `write!` macro
+use subtle::ConstantTimeEq;
 
 use crate::errors::AppError;
 
@@ -17,23 +20,87 @@ pub fn generate_session_token() -> String {
 }
 
 pub fn hash_password(password: &str) -> Result<String, AppError> {
-    let password_bytes = password.as_bytes();
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|_| AppError::InternalServerError)?;
+    Ok(hash.to_string())
+}
 
-    // Use SHA-1 for hashing
+// 移行前のパスワードはソルトなしの SHA-1 (16 進数文字列) で保存されている
+fn hash_password_legacy(password: &str) -> String {
     let mut hasher = Sha1::new();
-    hasher.update(password_bytes);
+    hasher.update(password.as_bytes());
     let result = hasher.finalize();
     let mut hash_string = String::new();
     for byte in result {
         write!(&mut hash_string, "{:02x}", byte).unwrap();
     }
-    Ok(hash_string)
+    hash_string
 }
 
 pub fn verify_password(hashed_password: &str, input_password: &str) -> Result<bool, AppError> {
-    let input_password_bytes = input_password.as_bytes();
+    if needs_rehash(hashed_password) {
+        // 比較にかかる時間からハッシュが推測されないよう、定数時間で比較する
+        let input_password_hash = hash_password_legacy(input_password);
+        return Ok(hashed_password
+            .as_bytes()
+            .ct_eq(input_password_hash.as_bytes())
+            .into());
+    }
+
+    let parsed_hash =
+        PasswordHash::new(hashed_password).map_err(|_| AppError::InternalServerError)?;
+    Ok(Argon2::default()
+        .verify_password(input_password.as_bytes(), &parsed_hash)
+        .is_ok())
+}
 
-    // Hash the input password and compare
-    let input_password_hash = hash_password(input_password)?;
-    Ok(hashed_password == input_password_hash)
+// Argon2id の PHC 文字列でなければ、ログイン時に再ハッシュする
+pub fn needs_rehash(hashed_password: &str) -> bool {
+    !hashed_password.starts_with("$argon2id$")
+}
+
+// 担当エリアが決まっている操作者の場合、指定されたエリアを担当エリアに限定する
+pub fn restrict_area(
+    requested_area_id: Option<i32>,
+    allowed_area_id: Option<i32>,
+) -> Result<Option<i32>, AppError> {
+    match (requested_area_id, allowed_area_id) {
+        (Some(requested), Some(allowed)) if requested != allowed => Err(AppError::Forbidden),
+        (requested, None) => Ok(requested),
+        (_, allowed) => Ok(allowed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_argon2id_hash() {
+        let hashed_password = hash_password("password").unwrap();
+        assert!(hashed_password.starts_with("$argon2id$"));
+        assert!(!needs_rehash(&hashed_password));
+        assert!(verify_password(&hashed_password, "password").unwrap());
+        assert!(!verify_password(&hashed_password, "wrong").unwrap());
+    }
+
+    #[test]
+    fn verifies_legacy_sha1_hash() {
+        // "password" の SHA-1
+        let hashed_password = "5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8";
+        assert!(needs_rehash(hashed_password));
+        assert!(verify_password(hashed_password, "password").unwrap());
+        assert!(!verify_password(hashed_password, "wrong").unwrap());
+    }
+
+    #[test]
+    fn restricts_area_to_allowed_area() {
+        assert_eq!(restrict_area(Some(1), None).unwrap(), Some(1));
+        assert_eq!(restrict_area(None, None).unwrap(), None);
+        assert_eq!(restrict_area(None, Some(2)).unwrap(), Some(2));
+        assert_eq!(restrict_area(Some(2), Some(2)).unwrap(), Some(2));
+        assert!(restrict_area(Some(1), Some(2)).is_err());
+    }
 }
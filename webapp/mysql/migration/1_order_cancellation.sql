@@ -0,0 +1,5 @@
+-- 依頼のキャンセル情報を記録する
+
+ALTER TABLE orders ADD COLUMN cancelled_by INT NULL;
+ALTER TABLE orders ADD COLUMN cancelled_at DATETIME NULL;
+ALTER TABLE orders ADD COLUMN cancel_reason VARCHAR(255) NULL;
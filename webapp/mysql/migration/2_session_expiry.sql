@@ -0,0 +1,8 @@
+-- セッションに有効期限を持たせる
+
+ALTER TABLE sessions ADD COLUMN created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE sessions ADD COLUMN last_seen_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE sessions ADD COLUMN expires_at DATETIME NOT NULL DEFAULT (CURRENT_TIMESTAMP + INTERVAL 1 DAY);
+
+ALTER TABLE sessions ADD INDEX index_session_user_id(user_id);
+ALTER TABLE sessions ADD INDEX index_session_expires_at(expires_at);
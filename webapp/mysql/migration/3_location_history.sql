@@ -0,0 +1,3 @@
+-- レッカー車ごとの走行履歴を時刻順に取得する
+
+ALTER TABLE locations ADD INDEX index_location_tow_truck_id_timestamp(tow_truck_id, timestamp);
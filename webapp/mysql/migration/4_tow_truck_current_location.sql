@@ -0,0 +1,19 @@
+-- レッカー車の現在地を tow_trucks に持たせ、locations の最新行を毎回探さなくて済むようにする
+
+ALTER TABLE tow_trucks ADD COLUMN node_id INT NULL;
+ALTER TABLE tow_trucks ADD COLUMN location_updated_at DATETIME NULL;
+
+UPDATE tow_trucks tt
+SET
+    tt.node_id = (
+        SELECT l.node_id FROM locations l
+        WHERE l.tow_truck_id = tt.id
+        ORDER BY l.timestamp DESC, l.id DESC
+        LIMIT 1
+    ),
+    tt.location_updated_at = (
+        SELECT MAX(l.timestamp) FROM locations l
+        WHERE l.tow_truck_id = tt.id
+    );
+
+ALTER TABLE tow_trucks ADD INDEX index_tow_truck_node_id(node_id);
@@ -0,0 +1,12 @@
+-- エリアごとの自動配車の設定
+
+CREATE TABLE IF NOT EXISTS auto_dispatch_settings (
+    area_id INT PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    priority VARCHAR(50) NOT NULL DEFAULT 'order_time'
+);
+
+-- 自動配車を行うシステムユーザー。ログインはできず、各エリアのディスパッチャーとして配車を記録する。
+INSERT INTO users (username, password, role) VALUES ('system', '!', 'dispatcher');
+INSERT INTO dispatchers (user_id, area_id)
+SELECT u.id, a.id FROM users u CROSS JOIN areas a WHERE u.username = 'system';
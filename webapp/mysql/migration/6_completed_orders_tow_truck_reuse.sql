@@ -0,0 +1,5 @@
+-- 対応を終えたレッカー車は再び配車されるため、completed_orders.tow_truck_id の一意制約を外す。
+-- 外部キーが使うインデックスを先に追加してから、UNIQUE インデックスを削除する。
+
+ALTER TABLE completed_orders ADD INDEX index_completed_orders_tow_truck_id(tow_truck_id);
+ALTER TABLE completed_orders DROP INDEX tow_truck_id;